@@ -0,0 +1,94 @@
+// Compares catr with the system cat on a large file. The size in MiB comes
+// from CATR_BENCH_MB, 2048 by default:
+//
+//   CATR_BENCH_MB=4096 cargo bench --bench throughput
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const CATR: &str = env!("CARGO_BIN_EXE_catr");
+const RUNS: usize = 3;
+
+fn main() -> io::Result<()> {
+    let mb: u64 = env::var("CATR_BENCH_MB")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2048);
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = dir.join("throughput.in");
+    let output = dir.join("throughput.out");
+    make_input(&input, mb)?;
+
+    println!("{} MiB, best of {} runs", mb, RUNS);
+    for sink in ["/dev/null", "pipe", "file"] {
+        let cat = best(RUNS, || time("cat", &input, sink, &output))?;
+        let catr = best(RUNS, || time(CATR, &input, sink, &output))?;
+        println!(
+            "{:<10} cat {:>8.0} MB/s   catr {:>8.0} MB/s   ratio {:.2}",
+            sink,
+            rate(mb, cat),
+            rate(mb, catr),
+            cat.as_secs_f64() / catr.as_secs_f64()
+        );
+    }
+
+    fs::remove_file(&output).ok();
+    fs::remove_file(&input)
+}
+
+// Lines of text, so the file looks like something cat would normally read
+fn make_input(path: &Path, mb: u64) -> io::Result<()> {
+    let size = mb * 1024 * 1024;
+    if fs::metadata(path).map(|m| m.len() == size).unwrap_or(false) {
+        return Ok(());
+    }
+    let line = b"The quick brown fox jumps over the lazy dog, 0123456789 times.\n";
+    let mut block = Vec::with_capacity(1024 * 1024);
+    while block.len() < block.capacity() {
+        let n = line.len().min(block.capacity() - block.len());
+        block.extend_from_slice(&line[..n]);
+    }
+    let mut file = BufWriter::new(File::create(path)?);
+    for _ in 0..mb {
+        file.write_all(&block)?;
+    }
+    file.flush()
+}
+
+fn time(program: &str, input: &Path, sink: &str, output: &Path) -> io::Result<Duration> {
+    let stdout = match sink {
+        "/dev/null" => Stdio::from(File::create("/dev/null")?),
+        "pipe" => Stdio::piped(),
+        _ => Stdio::from(File::create(output)?),
+    };
+    let start = Instant::now();
+    let mut child = Command::new(program).arg(input).stdout(stdout).spawn()?;
+    // Drain the pipe the way a reader on the other end would
+    if let Some(mut pipe) = child.stdout.take() {
+        let mut buffer = vec![0; 128 * 1024];
+        while pipe.read(&mut buffer)? > 0 {}
+    }
+    let status = child.wait()?;
+    let elapsed = start.elapsed();
+    if !status.success() {
+        return Err(io::Error::other(format!("{} failed: {}", program, status)));
+    }
+    Ok(elapsed)
+}
+
+fn best(runs: usize, mut run: impl FnMut() -> io::Result<Duration>) -> io::Result<Duration> {
+    let mut best = run()?;
+    for _ in 1..runs {
+        best = best.min(run()?);
+    }
+    Ok(best)
+}
+
+fn rate(mb: u64, elapsed: Duration) -> f64 {
+    mb as f64 * 1024.0 * 1024.0 / 1e6 / elapsed.as_secs_f64()
+}
@@ -26,4 +26,39 @@ cat -b $ALL > $OUT_DIR/all.b.out
 
 cat    < $BUSTLE > $OUT_DIR/$(basename $BUSTLE).stdin.out
 cat -n < $BUSTLE > $OUT_DIR/$(basename $BUSTLE).n.stdin.out
-cat -b < $BUSTLE > $OUT_DIR/$(basename $BUSTLE).b.stdin.out
\ No newline at end of file
+cat -b < $BUSTLE > $OUT_DIR/$(basename $BUSTLE).b.stdin.out
+CONTROL="$ROOT/control.txt"
+for FLAG in v E T A; do
+    cat -$FLAG $CONTROL > $OUT_DIR/$(basename $CONTROL).$FLAG.out
+done
+cat -nA $CONTROL > $OUT_DIR/$(basename $CONTROL).nA.out
+
+BLANKS="$ROOT/blanks.txt"
+cat -s  $BLANKS > $OUT_DIR/$(basename $BLANKS).s.out
+cat -sn $BLANKS > $OUT_DIR/$(basename $BLANKS).sn.out
+cat -sb $BLANKS > $OUT_DIR/$(basename $BLANKS).sb.out
+cat -sn $BLANKS $EMPTY $BLANKS > $OUT_DIR/$(basename $BLANKS).twice.sn.out
+
+# The unterminated last line of control.txt runs into the next file
+cat -n $CONTROL $FOX > $OUT_DIR/control.fox.n.out
+
+# Every byte value, CRLF endings and no final newline
+BINARY="$ROOT/binary.bin"
+cat -n $BINARY > $OUT_DIR/$(basename $BINARY).n.out
+cat -b $BINARY > $OUT_DIR/$(basename $BINARY).b.out
+
+# Line and pattern selection, cut from cat's own output to keep the numbers
+cat -n $BUSTLE | sed -n '2,4p' > $OUT_DIR/$(basename $BUSTLE).lines.n.out
+sed -n '6,$p' $BUSTLE > $OUT_DIR/$(basename $BUSTLE).lines_open.out
+sed -n '/morning/,/upon/p' $BUSTLE > $OUT_DIR/$(basename $BUSTLE).from_to.out
+cat -n $BUSTLE | sed -n '/sweeping/,$p' > $OUT_DIR/$(basename $BUSTLE).from.n.out
+cat -n $FOX $SPIDERS | sed -n '2,3p' > $OUT_DIR/fox.spiders.lines.n.out
+
+# Highlighting only adds escapes, which the tests strip before comparing
+TOML="$ROOT/sample.toml"
+cat -n $TOML > $OUT_DIR/$(basename $TOML).n.out
+
+for FILE in $FOX $SPIDERS; do
+    echo "File: $FILE"
+    cat $FILE
+done > $OUT_DIR/fox.spiders.header.out
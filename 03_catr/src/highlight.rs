@@ -0,0 +1,100 @@
+use crate::MyResult;
+use std::path::Path;
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Style, Theme, ThemeSet},
+    parsing::{SyntaxDefinition, SyntaxReference, SyntaxSet, SyntaxSetBuilder},
+};
+
+const TOML: &str = include_str!("toml.sublime-syntax");
+const THEME: &str = "base16-ocean.dark";
+
+// The bundled syntaxes and our own are kept apart, since merging them would
+// rebuild the bundled set on every start
+pub struct Highlighter {
+    syntax_sets: [SyntaxSet; 2],
+    theme: Theme,
+    language: Option<String>,
+}
+
+type Syntax<'a> = (&'a SyntaxSet, &'a SyntaxReference);
+
+impl Highlighter {
+    // LANG may be a syntax name or an extension, such as rust or rs
+    pub fn new(language: Option<&str>) -> MyResult<Self> {
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(SyntaxDefinition::load_from_str(TOML, true, None)?);
+        let syntax_sets = [SyntaxSet::load_defaults_newlines(), builder.build()];
+        let language = match language {
+            Some(token) => Some(
+                find(&syntax_sets, |set| set.find_syntax_by_token(token))
+                    .ok_or_else(|| format!("Unknown language \"{}\"", token))?
+                    .1
+                    .name
+                    .clone(),
+            ),
+            None => None,
+        };
+        let theme = ThemeSet::load_defaults()
+            .themes
+            .remove(THEME)
+            .ok_or_else(|| format!("Missing theme \"{}\"", THEME))?;
+        Ok(Highlighter {
+            syntax_sets,
+            theme,
+            language,
+        })
+    }
+
+    // The syntax comes from --highlight=LANG, then the extension or the whole
+    // file name (Makefile, Cargo.lock), and failing those the first line
+    pub fn for_file(&self, filename: &str) -> FileHighlighter<'_> {
+        let path = Path::new(filename);
+        let by_name = |name: Option<&std::ffi::OsStr>| {
+            let name = name?.to_str()?;
+            find(&self.syntax_sets, |set| set.find_syntax_by_extension(name))
+        };
+        let syntax = match &self.language {
+            Some(language) => find(&self.syntax_sets, |set| set.find_syntax_by_name(language)),
+            None => by_name(path.extension()).or_else(|| by_name(path.file_name())),
+        };
+        FileHighlighter {
+            highlighter: self,
+            syntax,
+            lines: None,
+        }
+    }
+}
+
+// Parser state for one file, which has to see every line in order
+pub struct FileHighlighter<'a> {
+    highlighter: &'a Highlighter,
+    syntax: Option<Syntax<'a>>,
+    lines: Option<(&'a SyntaxSet, HighlightLines<'a>)>,
+}
+
+impl FileHighlighter<'_> {
+    // Takes the line with its newline, as the bundled syntaxes expect
+    pub fn line<'b>(&mut self, line: &'b str) -> Vec<(Style, &'b str)> {
+        let highlighter = self.highlighter;
+        let (set, lines) = self.lines.get_or_insert_with(|| {
+            let sets = &highlighter.syntax_sets;
+            let (set, syntax) = self
+                .syntax
+                .or_else(|| find(sets, |set| set.find_syntax_by_first_line(line)))
+                .unwrap_or_else(|| (&sets[0], sets[0].find_syntax_plain_text()));
+            (set, HighlightLines::new(syntax, &highlighter.theme))
+        });
+        lines
+            .highlight_line(line, set)
+            .unwrap_or_else(|_| vec![(Style::default(), line)])
+    }
+}
+
+fn find<'a>(
+    sets: &'a [SyntaxSet],
+    lookup: impl Fn(&'a SyntaxSet) -> Option<&'a SyntaxReference>,
+) -> Option<Syntax<'a>> {
+    sets.iter()
+        .find_map(|set| lookup(set).map(|syntax| (set, syntax)))
+}
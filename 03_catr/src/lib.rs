@@ -1,50 +1,413 @@
 use clap::{Arg, ArgAction, Command};
+use highlight::{FileHighlighter, Highlighter};
+use regex::bytes::Regex;
 use std::{
+    borrow::Cow,
     error::Error,
+    fmt,
     fs::File,
-    io::{self, BufRead, BufReader},
+    io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write},
+    str,
 };
+use syntect::highlighting::Style;
+
+mod highlight;
+
+#[cfg(target_os = "linux")]
+mod zero_copy;
+
+// Buffer for copying when the kernel cannot do it for us
+const COPY_BUFFER_SIZE: usize = 128 * 1024;
 
 #[derive(Debug)]
 pub struct Config {
     files: Vec<String>,
     number_lines: bool,
     number_nonblank_lines: bool,
+    show_nonprinting: bool,
+    show_ends: bool,
+    show_tabs: bool,
+    squeeze_blank: bool,
+    line_range: Option<(usize, usize)>,
+    from: Option<Regex>,
+    to: Option<Regex>,
+    highlight: bool,
+    language: Option<String>,
+    header: bool,
+    color: bool,
+}
+
+impl Config {
+    // Without formatting the input is copied through byte for byte
+    fn is_plain(&self) -> bool {
+        !(self.number_lines
+            || self.number_nonblank_lines
+            || self.show_nonprinting
+            || self.show_ends
+            || self.show_tabs
+            || self.squeeze_blank
+            || self.is_selecting()
+            || self.highlight)
+    }
+
+    fn is_selecting(&self) -> bool {
+        self.line_range.is_some() || self.from.is_some() || self.to.is_some()
+    }
+
+    // Decides whether the next input line is shown. The line matching --from
+    // opens the span and --to is looked for from the line after it on, or
+    // from the first line when there is no --from.
+    fn select(&self, position: &mut Position, content: &[u8]) -> bool {
+        let in_range = self
+            .line_range
+            .is_none_or(|(start, end)| (start..=end).contains(&position.input_line));
+        let in_span = match position.span {
+            Span::Done => false,
+            Span::Before
+                if self
+                    .from
+                    .as_ref()
+                    .is_some_and(|from| !from.is_match(content)) =>
+            {
+                false
+            }
+            Span::Before if self.from.is_some() => {
+                position.span = Span::Inside;
+                true
+            }
+            _ => {
+                if self.to.as_ref().is_some_and(|to| to.is_match(content)) {
+                    position.span = Span::Done;
+                }
+                true
+            }
+        };
+        in_range && in_span
+    }
+
+    // Nothing after the current line can be selected any more
+    fn is_finished(&self, position: &Position) -> bool {
+        position.span == Span::Done
+            || self
+                .line_range
+                .is_some_and(|(_, end)| position.input_line >= end)
+    }
+}
+
+// Where the input stands relative to --from and --to
+#[derive(Debug, PartialEq)]
+enum Span {
+    Before,
+    Inside,
+    Done,
+}
+
+// Where the output stands, carried from one file to the next as with
+// concatenating the files first
+#[derive(Debug)]
+struct Position {
+    line_num: usize,
+    at_line_start: bool,
+    prev_blank: bool,
+    input_line: usize,
+    selected: bool,
+    span: Span,
+}
+
+// Inputs stay unbuffered until we know whether the plain copy can hand
+// their descriptors to the kernel
+enum Input {
+    Stdin(io::Stdin),
+    File(File),
+}
+
+impl Input {
+    fn into_reader(self) -> Box<dyn BufRead> {
+        match self {
+            Input::Stdin(stdin) => Box::new(BufReader::new(stdin)),
+            Input::File(file) => Box::new(BufReader::new(file)),
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Stdin(stdin) => stdin.read(buf),
+            Input::File(file) => file.read(buf),
+        }
+    }
 }
 
+// Why an input could not be read at all, reported before moving on to the
+// next one
+#[derive(Debug)]
+pub enum OpenError {
+    NotFound(String),
+    PermissionDenied(String),
+    IsDirectory(String),
+    Io(String, io::Error),
+}
+
+impl OpenError {
+    fn new(filename: &str, err: io::Error) -> Self {
+        let filename = filename.to_string();
+        match err.kind() {
+            io::ErrorKind::NotFound => OpenError::NotFound(filename),
+            io::ErrorKind::PermissionDenied => OpenError::PermissionDenied(filename),
+            io::ErrorKind::IsADirectory => OpenError::IsDirectory(filename),
+            _ => OpenError::Io(filename, err),
+        }
+    }
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpenError::NotFound(filename) => write!(f, "{}: No such file or directory", filename),
+            OpenError::PermissionDenied(filename) => write!(f, "{}: Permission denied", filename),
+            OpenError::IsDirectory(filename) => write!(f, "{}: Is a directory", filename),
+            OpenError::Io(filename, e) => write!(f, "{}: {}", filename, e),
+        }
+    }
+}
+
+impl Error for OpenError {}
+
 type MyResult<T> = Result<T, Box<dyn Error>>;
 
-pub fn run(config: Config) -> MyResult<()> {
-    for filename in config.files {
-        match open(&filename) {
-            Err(err) => eprint!("Fail to open {}: {}", filename, err),
-            Ok(file) => {
-                let mut last_num = 0;
-                for (line_num, line) in file.lines().enumerate() {
-                    let line = line?;
-                    if config.number_lines {
-                        println!("{:>6}\t{}", line_num + 1, line);
-                    } else if config.number_nonblank_lines {
-                        if !line.is_empty() {
-                            last_num += 1;
-                            println!("{:>6}\t{}", last_num, line);
-                        } else {
-                            println!();
-                        }
-                    } else {
-                        println!("{}", line);
-                    }
+// Returns the exit code, 1 when any input could not be opened
+pub fn run(config: Config) -> MyResult<i32> {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut position = Position {
+        line_num: 0,
+        at_line_start: true,
+        prev_blank: false,
+        input_line: 0,
+        selected: true,
+        span: Span::Before,
+    };
+    // LANG is checked even when color is off and nothing gets highlighted
+    let highlighter = if config.highlight || config.language.is_some() {
+        Some(Highlighter::new(config.language.as_deref())?).filter(|_| config.highlight)
+    } else {
+        None
+    };
+    let mut code = 0;
+    for filename in &config.files {
+        match open(filename) {
+            Err(err) => {
+                out.flush()?;
+                eprintln!("{}", err);
+                code = 1;
+            }
+            Ok(input) => {
+                if config.header {
+                    write_header(&config, filename, &mut out)?;
+                }
+                if config.is_plain() {
+                    out.flush()?;
+                    copy_plain(input, out.get_mut())?;
+                } else {
+                    let highlighter = highlighter.as_ref().map(|h| h.for_file(filename));
+                    cat_file(
+                        &config,
+                        input.into_reader(),
+                        highlighter,
+                        &mut position,
+                        &mut out,
+                    )?;
                 }
             }
         }
     }
+    out.flush()?;
+    Ok(code)
+}
+
+fn write_header(config: &Config, filename: &str, out: &mut impl Write) -> io::Result<()> {
+    if config.color {
+        writeln!(out, "File: \x1b[1m{}\x1b[0m", filename)
+    } else {
+        writeln!(out, "File: {}", filename)
+    }
+}
+
+fn copy_plain(mut input: Input, out: &mut io::StdoutLock) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsFd;
+        let fd = match &input {
+            Input::Stdin(stdin) => stdin.as_fd(),
+            Input::File(file) => file.as_fd(),
+        };
+        if zero_copy::copy(fd, out.as_fd())? {
+            return Ok(());
+        }
+    }
+
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        match input.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => out.write_all(&buffer[..n])?,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Works on raw lines so CRLF endings, a missing final newline and invalid
+// UTF-8 all come through
+fn cat_file(
+    config: &Config,
+    mut file: Box<dyn BufRead>,
+    mut highlighter: Option<FileHighlighter>,
+    position: &mut Position,
+    out: &mut impl Write,
+) -> MyResult<()> {
+    let mut line = vec![];
+    loop {
+        if position.at_line_start && config.is_selecting() && config.is_finished(position) {
+            break;
+        }
+        line.clear();
+        if file.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let (content, newline) = match line.strip_suffix(b"\n") {
+            Some(content) => (content, true),
+            None => (&line[..], false),
+        };
+        if position.at_line_start {
+            position.input_line += 1;
+            position.selected = config.select(position, content);
+        }
+        // Every line goes through the highlighter to keep its state right,
+        // though lines that are not UTF-8 come out uncolored
+        let styles = match (&mut highlighter, str::from_utf8(&line)) {
+            (Some(highlighter), Ok(line)) => Some(highlighter.line(line)),
+            _ => None,
+        };
+        // Lines left out are still counted, so the numbers shown are the
+        // ones cat -n would give them
+        //
+        // The rest of a line left unfinished by the previous file is not a
+        // new line, so it is neither numbered nor blank
+        let blank = position.at_line_start && newline && content.is_empty();
+        if config.squeeze_blank && blank && position.prev_blank {
+            continue;
+        }
+        let numbered = config.number_lines || (config.number_nonblank_lines && !blank);
+        if position.at_line_start && numbered {
+            position.line_num += 1;
+            if position.selected {
+                write!(out, "{:>6}\t", position.line_num)?;
+            }
+        }
+        position.at_line_start = newline;
+        position.prev_blank = blank;
+        if !position.selected {
+            continue;
+        }
+
+        // A CR before the newline shows up as ^M$, the tell of a CRLF file
+        let crlf = newline && config.show_ends && content.ends_with(b"\r");
+        let content = if crlf {
+            &content[..content.len() - 1]
+        } else {
+            content
+        };
+        match &styles {
+            Some(styles) => write_styled(config, styles, content.len(), out)?,
+            None => out.write_all(&render(config, content))?,
+        }
+        if crlf {
+            out.write_all(b"^M")?;
+        }
+        if newline {
+            if config.show_ends {
+                out.write_all(b"$")?;
+            }
+            out.write_all(b"\n")?;
+        }
+    }
     Ok(())
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+// Colors the first len bytes of the line in 24-bit ANSI, leaving the
+// newline and anything -E adds after it alone
+fn write_styled(
+    config: &Config,
+    styles: &[(Style, &str)],
+    len: usize,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    let mut rest = len;
+    let mut current = None;
+    for (style, text) in styles {
+        let text = &text.as_bytes()[..text.len().min(rest)];
+        rest -= text.len();
+        let color = style.foreground;
+        if current != Some(color) {
+            write!(out, "\x1b[38;2;{};{};{}m", color.r, color.g, color.b)?;
+            current = Some(color);
+        }
+        out.write_all(&render(config, text))?;
+        if rest == 0 {
+            break;
+        }
+    }
+    out.write_all(b"\x1b[0m")
+}
+
+fn render<'a>(config: &Config, content: &'a [u8]) -> Cow<'a, [u8]> {
+    if !config.show_nonprinting && !config.show_tabs {
+        return Cow::Borrowed(content);
+    }
+    let mut rendered = Vec::with_capacity(content.len());
+    for &byte in content {
+        match byte {
+            b'\t' if config.show_tabs => rendered.extend_from_slice(b"^I"),
+            b'\t' => rendered.push(byte),
+            _ if config.show_nonprinting => push_visible(&mut rendered, byte),
+            _ => rendered.push(byte),
+        }
+    }
+    Cow::Owned(rendered)
+}
+
+// Caret notation for control characters and M- for bytes with the high bit
+// set, as cat -v prints them
+fn push_visible(rendered: &mut Vec<u8>, byte: u8) {
+    let byte = if byte >= 128 {
+        rendered.extend_from_slice(b"M-");
+        byte - 128
+    } else {
+        byte
+    };
+    match byte {
+        0..=31 => rendered.extend_from_slice(&[b'^', byte + 64]),
+        127 => rendered.extend_from_slice(b"^?"),
+        _ => rendered.push(byte),
+    }
+}
+
+// Opening a directory works on Linux and only reading it fails, so check
+// for one up front
+fn open(filename: &str) -> Result<Input, OpenError> {
+    if filename == "-" {
+        return Ok(Input::Stdin(io::stdin()));
+    }
+    let file = File::open(filename).map_err(|e| OpenError::new(filename, e))?;
+    match file.metadata() {
+        Ok(metadata) if metadata.is_dir() => Err(OpenError::IsDirectory(filename.to_string())),
+        Ok(_) => Ok(Input::File(file)),
+        Err(e) => Err(OpenError::new(filename, e)),
     }
 }
 
@@ -76,8 +439,112 @@ pub fn get_args() -> MyResult<Config> {
                 .help("Number non-blank lines")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("squeeze_blank")
+                .short('s')
+                .long("squeeze-blank")
+                .help("Suppress repeated empty output lines")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_all")
+                .short('A')
+                .long("show-all")
+                .help("Equivalent to -vET")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_nonprinting")
+                .short('v')
+                .long("show-nonprinting")
+                .help("Use ^ and M- notation, except for LFD and TAB")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_ends")
+                .short('E')
+                .long("show-ends")
+                .help("Display $ at end of each line")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_tabs")
+                .short('T')
+                .long("show-tabs")
+                .help("Display TAB characters as ^I")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("lines")
+                .long("lines")
+                .value_name("START:END")
+                .help("Print only lines START through END"),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("REGEX")
+                .help("Start printing at the first line matching REGEX"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .value_name("REGEX")
+                .help("Stop printing after the next line matching REGEX"),
+        )
+        .arg(
+            Arg::new("highlight")
+                .long("highlight")
+                .value_name("LANG")
+                .help("Color syntax, detecting the language from the file name")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value(""),
+        )
+        .arg(
+            Arg::new("header")
+                .long("header")
+                .help("Print the file name before each file")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .visible_alias("colour")
+                .value_name("WHEN")
+                .help("Use colors for --highlight and --header")
+                .value_parser(["auto", "always", "never"])
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_value("auto")
+                .default_missing_value("auto"),
+        )
         .get_matches();
 
+    let line_range = matches
+        .get_one::<String>("lines")
+        .map(|val| parse_line_range(val))
+        .transpose()?;
+    let from = matches
+        .get_one::<String>("from")
+        .map(|val| Regex::new(val).map_err(|_| format!("Invalid --from \"{}\"", val)))
+        .transpose()?;
+    let to = matches
+        .get_one::<String>("to")
+        .map(|val| Regex::new(val).map_err(|_| format!("Invalid --to \"{}\"", val)))
+        .transpose()?;
+
+    let show_all = matches.get_flag("show_all");
+    // Colors only go to a terminal unless asked for
+    let color = match matches.get_one::<String>("color").unwrap().as_str() {
+        "always" => true,
+        "auto" => io::stdout().is_terminal(),
+        _ => false,
+    };
+    let language = matches
+        .get_one::<String>("highlight")
+        .filter(|lang| !lang.is_empty())
+        .cloned();
     Ok(Config {
         files: matches
             .get_many::<String>("files")
@@ -86,5 +553,47 @@ pub fn get_args() -> MyResult<Config> {
             .collect(),
         number_lines: matches.get_flag("number"),
         number_nonblank_lines: matches.get_flag("number_nonblank"),
+        show_nonprinting: show_all || matches.get_flag("show_nonprinting"),
+        show_ends: show_all || matches.get_flag("show_ends"),
+        show_tabs: show_all || matches.get_flag("show_tabs"),
+        squeeze_blank: matches.get_flag("squeeze_blank"),
+        line_range,
+        from,
+        to,
+        highlight: color && matches.contains_id("highlight"),
+        language,
+        header: matches.get_flag("header"),
+        color,
     })
 }
+
+// START:END counts from 1 and includes both ends; either may be left out
+fn parse_line_range(range: &str) -> MyResult<(usize, usize)> {
+    let error = || format!("illegal line range: \"{}\"", range);
+    let parse = |val: &str, default: usize| match val {
+        "" => Some(default),
+        _ if val.bytes().all(|b| b.is_ascii_digit()) => val.parse().ok().filter(|&n| n > 0),
+        _ => None,
+    };
+    let (start, end) = range.split_once(':').ok_or_else(error)?;
+    match (parse(start, 1), parse(end, usize::MAX)) {
+        (Some(start), Some(end)) if start <= end => Ok((start, end)),
+        _ => Err(error().into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpenError;
+    use std::io;
+
+    #[test]
+    fn test_open_error() {
+        let err = OpenError::new("secret.txt", io::ErrorKind::PermissionDenied.into());
+        assert!(matches!(&err, OpenError::PermissionDenied(name) if name == "secret.txt"));
+        assert_eq!(err.to_string(), "secret.txt: Permission denied");
+
+        let err = OpenError::new("missing.txt", io::ErrorKind::NotFound.into());
+        assert_eq!(err.to_string(), "missing.txt: No such file or directory");
+    }
+}
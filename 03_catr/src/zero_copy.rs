@@ -0,0 +1,80 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, BorrowedFd},
+    ptr,
+};
+
+// The most any one call is asked to move; the kernel caps it lower anyway
+const CHUNK: usize = 1 << 30;
+
+type Transfer = fn(i32, i32) -> isize;
+
+// Has the kernel move the data from input to output without passing it
+// through user space. copy_file_range needs two regular files, sendfile a
+// regular file to read, and splice a pipe on one side. Returns false if none
+// of them could start, so the caller copies the bytes itself.
+pub fn copy(input: BorrowedFd, output: BorrowedFd) -> io::Result<bool> {
+    let methods: [Transfer; 3] = [copy_file_range, sendfile, splice];
+    for method in methods {
+        if transfer(input.as_raw_fd(), output.as_raw_fd(), method)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn transfer(input: i32, output: i32, method: Transfer) -> io::Result<bool> {
+    let mut started = false;
+    loop {
+        match method(input, output) {
+            // Some files, such as those in /proc, report nothing to the
+            // kernel copy but still have data for read()
+            0 => return Ok(started),
+            n if n > 0 => started = true,
+            _ => {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    Some(
+                        libc::EINVAL
+                        | libc::ENOSYS
+                        | libc::EXDEV
+                        | libc::EBADF
+                        | libc::EOPNOTSUPP
+                        | libc::ESPIPE
+                        | libc::EPERM,
+                    ) if !started => return Ok(false),
+                    _ => return Err(err),
+                }
+            }
+        }
+    }
+}
+
+fn copy_file_range(input: i32, output: i32) -> isize {
+    // SAFETY: both descriptors are borrowed for the whole call, and the null
+    // offsets make the kernel use and advance the file positions instead of
+    // reading or writing our memory
+    unsafe { libc::copy_file_range(input, ptr::null_mut(), output, ptr::null_mut(), CHUNK, 0) }
+}
+
+fn sendfile(input: i32, output: i32) -> isize {
+    // SAFETY: as for copy_file_range, the descriptors outlive the call and the
+    // null offset means no pointer is dereferenced
+    unsafe { libc::sendfile(output, input, ptr::null_mut(), CHUNK) }
+}
+
+fn splice(input: i32, output: i32) -> isize {
+    // SAFETY: splice only moves data between the two borrowed descriptors, and
+    // with null offsets it touches no memory of ours
+    unsafe {
+        libc::splice(
+            input,
+            ptr::null_mut(),
+            output,
+            ptr::null_mut(),
+            CHUNK,
+            libc::SPLICE_F_MOVE,
+        )
+    }
+}
@@ -1,8 +1,12 @@
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
+use regex::Regex;
 use std::error::Error;
 use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::process::{self, Stdio};
 
 type TestResult = Result<(), Box<dyn Error>>;
 
@@ -11,6 +15,10 @@ const EMPTY: &str = "tests/inputs/empty.txt";
 const FOX: &str = "tests/inputs/fox.txt";
 const SPIDERS: &str = "tests/inputs/spiders.txt";
 const BUSTLE: &str = "tests/inputs/the-bustle.txt";
+const CONTROL: &str = "tests/inputs/control.txt";
+const BLANKS: &str = "tests/inputs/blanks.txt";
+const BINARY: &str = "tests/inputs/binary.bin";
+const TOML: &str = "tests/inputs/sample.toml";
 
 // --------------------------------------------------
 #[test]
@@ -43,18 +51,86 @@ fn gen_bad_file() -> String {
 #[test]
 fn skips_bad_file() -> TestResult {
     let bad = gen_bad_file();
-    let expected = format!("{}: .* [(]os error 2[)]", bad);
+    let expected = format!("{}: No such file or directory\n", bad);
     Command::cargo_bin(PRG)?
         .arg(&bad)
         .assert()
-        .success()
-        .stderr(predicate::str::is_match(expected)?);
+        .failure()
+        .stderr(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skips_bad_file_between_good() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args([FOX, &bad, SPIDERS])
+        .assert()
+        .failure()
+        .stdout(format!(
+            "{}{}",
+            fs::read_to_string(FOX)?,
+            fs::read_to_string(SPIDERS)?
+        ))
+        .stderr(format!("{}: No such file or directory\n", bad));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn skips_directory() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "tests/inputs", FOX])
+        .assert()
+        .failure()
+        .stdout(fs::read_to_string("tests/expected/fox.txt.n.out")?)
+        .stderr("tests/inputs: Is a directory\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn skips_unreadable_file() -> TestResult {
+    let unreadable = format!("{}/unreadable.txt", env!("CARGO_TARGET_TMPDIR"));
+    fs::write(&unreadable, "secret\n")?;
+    fs::set_permissions(&unreadable, fs::Permissions::from_mode(0o000))?;
+    // Nothing is unreadable to root, so OpenError's unit test covers this
+    if fs::File::open(&unreadable).is_ok() {
+        eprintln!("skipping skips_unreadable_file: running as root");
+        return Ok(());
+    }
+    Command::cargo_bin(PRG)?
+        .args([&unreadable, FOX])
+        .assert()
+        .failure()
+        .stdout(fs::read_to_string(FOX)?)
+        .stderr(format!("{}: Permission denied\n", unreadable));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reports_each_bad_file() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args([&bad, "tests", &bad])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(format!(
+            "{0}: No such file or directory\n\
+             tests: Is a directory\n\
+             {0}: No such file or directory\n",
+            bad
+        ));
     Ok(())
 }
 
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
-    let expected = fs::read_to_string(expected_file)?;
+    let expected = fs::read(expected_file)?;
     Command::cargo_bin(PRG)?
         .args(args)
         .assert()
@@ -194,3 +270,282 @@ fn all_n() -> TestResult {
 fn all_b() -> TestResult {
     run(&[FOX, SPIDERS, BUSTLE, "-b"], "tests/expected/all.b.out")
 }
+
+// --------------------------------------------------
+#[test]
+fn control_v() -> TestResult {
+    run(&["-v", CONTROL], "tests/expected/control.txt.v.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn control_e() -> TestResult {
+    run(&["-E", CONTROL], "tests/expected/control.txt.E.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn control_t() -> TestResult {
+    run(&["-T", CONTROL], "tests/expected/control.txt.T.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn control_a() -> TestResult {
+    run(&["--show-all", CONTROL], "tests/expected/control.txt.A.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn control_n_a() -> TestResult {
+    run(
+        &["-n", "-vET", CONTROL],
+        "tests/expected/control.txt.nA.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn blanks_s() -> TestResult {
+    run(&["-s", BLANKS], "tests/expected/blanks.txt.s.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn blanks_s_n() -> TestResult {
+    run(&["-s", "-n", BLANKS], "tests/expected/blanks.txt.sn.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn blanks_s_b() -> TestResult {
+    run(
+        &["--squeeze-blank", "-b", BLANKS],
+        "tests/expected/blanks.txt.sb.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn blanks_twice_s_n() -> TestResult {
+    run(
+        &["-sn", BLANKS, EMPTY, BLANKS],
+        "tests/expected/blanks.txt.twice.sn.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn control_fox_n() -> TestResult {
+    run(&["-n", CONTROL, FOX], "tests/expected/control.fox.n.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_passthrough() -> TestResult {
+    run(&[BINARY], BINARY)
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_passthrough_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .write_stdin(fs::read(BINARY)?)
+        .assert()
+        .success()
+        .stdout(fs::read(BINARY)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+// With a regular file for stdout the kernel does the copying, so check that
+// each input lands after the one before it
+fn run_to_file(name: &str, args: &[&str], stdin: Stdio) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = format!("{}/{}", env!("CARGO_TARGET_TMPDIR"), name);
+    let status = process::Command::new(env!("CARGO_BIN_EXE_catr"))
+        .args(args)
+        .stdin(stdin)
+        .stdout(Stdio::from(fs::File::create(&output)?))
+        .status()?;
+    assert!(status.success());
+    Ok(fs::read(output)?)
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_to_file() -> TestResult {
+    let mut expected = fs::read(BINARY)?;
+    expected.extend(fs::read(FOX)?);
+    expected.extend(fs::read(BINARY)?);
+    let output = run_to_file("binary_to_file.out", &[BINARY, FOX, BINARY], Stdio::null())?;
+    assert_eq!(output, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_stdin_file_to_file() -> TestResult {
+    let stdin = Stdio::from(fs::File::open(BINARY)?);
+    let output = run_to_file("binary_stdin_file.out", &[], stdin)?;
+    assert_eq!(output, fs::read(BINARY)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_stdin_pipe_to_file() -> TestResult {
+    // The pipe on stdin comes from a writer that has already finished
+    let mut writer = process::Command::new(env!("CARGO_BIN_EXE_catr"))
+        .arg(BINARY)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdin = Stdio::from(writer.stdout.take().unwrap());
+    let output = run_to_file("binary_stdin_pipe.out", &["-"], stdin)?;
+    assert!(writer.wait()?.success());
+    assert_eq!(output, fs::read(BINARY)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_n() -> TestResult {
+    run(&["-n", BINARY], "tests/expected/binary.bin.n.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_b() -> TestResult {
+    run(&["-b", BINARY], "tests/expected/binary.bin.b.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_lines_n() -> TestResult {
+    run(
+        &["-n", "--lines=2:4", BUSTLE],
+        "tests/expected/the-bustle.txt.lines.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_lines_open() -> TestResult {
+    run(
+        &["--lines", "6:", BUSTLE],
+        "tests/expected/the-bustle.txt.lines_open.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_from_to() -> TestResult {
+    run(
+        &["--from=morning", "--to=upon", BUSTLE],
+        "tests/expected/the-bustle.txt.from_to.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_from_n() -> TestResult {
+    run(
+        &["-n", "--from=sweeping", BUSTLE],
+        "tests/expected/the-bustle.txt.from.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fox_spiders_lines_n() -> TestResult {
+    run(
+        &["-n", "--lines=2:3", FOX, SPIDERS],
+        "tests/expected/fox.spiders.lines.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_lines() -> TestResult {
+    for range in ["4:2", "0:2", "+1:2", "3", "a:b"] {
+        Command::cargo_bin(PRG)?
+            .args([&format!("--lines={}", range), BUSTLE])
+            .assert()
+            .failure()
+            .stderr(format!("illegal line range: \"{}\"\n", range));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_from() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--from=(", BUSTLE])
+        .assert()
+        .failure()
+        .stderr("Invalid --from \"(\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn highlight_off_without_tty() -> TestResult {
+    run(&["--highlight", FOX], "tests/expected/fox.txt.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn toml_highlight_n() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["-n", "--highlight", "--color=always", TOML])
+        .output()?;
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.starts_with("     1\t\x1b[38;2;"));
+    // Blank lines carry no color at all
+    assert!(stdout
+        .lines()
+        .filter(|line| !line.ends_with('\t'))
+        .all(|line| line.ends_with("\x1b[0m")));
+    let plain = Regex::new("\x1b\\[[0-9;]*m")?.replace_all(&stdout, "");
+    assert_eq!(
+        plain,
+        fs::read_to_string("tests/expected/sample.toml.n.out")?
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fox_spiders_header() -> TestResult {
+    run(
+        &["--header", FOX, SPIDERS],
+        "tests/expected/fox.spiders.header.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_unknown_language() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--highlight=klingon", "--color=always", TOML])
+        .assert()
+        .failure()
+        .stderr("Unknown language \"klingon\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_unknown_language_without_color() -> TestResult {
+    for color in ["--color=never", "--color=auto"] {
+        Command::cargo_bin(PRG)?
+            .args(["--highlight=klingon", color, TOML])
+            .assert()
+            .failure()
+            .stdout("")
+            .stderr("Unknown language \"klingon\"\n");
+    }
+    Ok(())
+}
@@ -1,16 +1,23 @@
 use clap::{Arg, ArgAction, Command};
 use std::io::Read;
 use std::{
+    collections::VecDeque,
     error::Error,
     fs::File,
-    io::{self, BufRead, BufReader},
+    io::{self, BufRead, BufReader, Write},
 };
 
 #[derive(Debug)]
 pub struct Config {
     files: Vec<String>,
-    lines: usize,
-    bytes: Option<usize>,
+    lines: Count,
+    bytes: Option<Count>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Count {
+    First(usize),
+    AllButLast(usize),
 }
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
@@ -27,6 +34,7 @@ pub fn get_args() -> MyResult<Config> {
                 .value_name("LINES")
                 .help("Number of Lines")
                 .num_args(0..=1)
+                .allow_hyphen_values(true)
                 .default_value("10")
                 .default_missing_value("10"),
         )
@@ -37,7 +45,8 @@ pub fn get_args() -> MyResult<Config> {
                 .value_name("BYTES")
                 .conflicts_with("lines")
                 .help("Number of Line")
-                .num_args(1),
+                .num_args(1)
+                .allow_hyphen_values(true),
         )
         .arg(
             Arg::new("files")
@@ -50,13 +59,13 @@ pub fn get_args() -> MyResult<Config> {
 
     let lines = matches
         .get_one::<String>("lines")
-        .map(|x| parse_positive_int(x.as_str()))
+        .map(|x| parse_count(x.as_str()))
         .transpose()
         .map_err(|e| format!("illegal line count -- {}", e))?;
 
     let bytes = matches
         .get_one::<String>("bytes")
-        .map(|x| parse_positive_int(x.as_str()))
+        .map(|x| parse_count(x.as_str()))
         .transpose()
         .map_err(|e| format!("illegal byte count -- {}", e))?;
 
@@ -86,22 +95,27 @@ pub fn run(config: Config) -> MyResult<()> {
                     )
                 }
 
-                if let Some(num_bytes) = config.bytes {
-                    // let mut handle = file.take(num_bytes as u64);
-                    // let mut buffer = vec![0; num_bytes];
-                    // let bytes_read = handle.read(&mut buffer)?;
-                    // print!("{}", String::from_utf8_lossy(&buffer[..bytes_read]));
-                    let bytes: Result<Vec<_>, _> = file.bytes().take(num_bytes).collect();
-                    print!("{}", String::from_utf8_lossy(&bytes?));
-                } else {
-                    let mut line = String::new();
-                    for _ in 0..config.lines {
-                        let bytes = file.read_line(&mut line)?;
-                        if bytes == 0 {
-                            break;
+                match (&config.bytes, &config.lines) {
+                    (Some(Count::First(num_bytes)), _) => {
+                        let mut handle = file.take(*num_bytes as u64);
+                        io::copy(&mut handle, &mut io::stdout().lock())?;
+                    }
+                    (Some(Count::AllButLast(num_bytes)), _) => {
+                        print_all_but_last_bytes(file, *num_bytes)?
+                    }
+                    (None, Count::First(num_lines)) => {
+                        let mut line = String::new();
+                        for _ in 0..*num_lines {
+                            let bytes = file.read_line(&mut line)?;
+                            if bytes == 0 {
+                                break;
+                            }
+                            print!("{}", line);
+                            line.clear();
                         }
-                        print!("{}", line);
-                        line.clear();
+                    }
+                    (None, Count::AllButLast(num_lines)) => {
+                        print_all_but_last_lines(file, *num_lines)?
                     }
                 }
             }
@@ -110,11 +124,110 @@ pub fn run(config: Config) -> MyResult<()> {
     Ok(())
 }
 
+// Both hold back only the last K lines or bytes while streaming the rest,
+// so memory follows K and not the length of the input
+fn print_all_but_last_bytes(mut file: impl BufRead, num_bytes: usize) -> MyResult<()> {
+    let mut out = io::stdout().lock();
+    let mut held = VecDeque::new();
+    loop {
+        let buffer = file.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        let len = buffer.len();
+        held.extend(buffer);
+        file.consume(len);
+
+        if held.len() > num_bytes {
+            let excess = held.len() - num_bytes;
+            let (front, back) = held.as_slices();
+            let from_front = excess.min(front.len());
+            out.write_all(&front[..from_front])?;
+            out.write_all(&back[..excess - from_front])?;
+            held.drain(..excess);
+        }
+    }
+    Ok(())
+}
+
+fn print_all_but_last_lines(mut file: impl BufRead, num_lines: usize) -> MyResult<()> {
+    let mut out = io::stdout().lock();
+    let mut held = VecDeque::new();
+    loop {
+        let mut line = vec![];
+        if file.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        held.push_back(line);
+        if held.len() > num_lines {
+            out.write_all(&held.pop_front().unwrap())?;
+        }
+    }
+    Ok(())
+}
+
+// A leading '-' asks for all but the last K, which may be 0
+fn parse_count(val: &str) -> MyResult<Count> {
+    match val.strip_prefix('-') {
+        Some(num) if num.starts_with(|c: char| c.is_ascii_digit()) => parse_size(num)
+            .map(Count::AllButLast)
+            .map_err(|e| From::from(format!("-{}", e))),
+        Some(_) => Err(From::from(val)),
+        None => parse_positive_int(val).map(Count::First),
+    }
+}
+
 fn parse_positive_int(val: &str) -> MyResult<usize> {
-    match val.parse() {
-        Ok(n) if n > 0 => Ok(n),
-        _ => Err(From::from(val)),
+    match parse_size(val)? {
+        0 => Err(From::from(val)),
+        n => Ok(n),
+    }
+}
+
+// A number with GNU's multiplier suffixes: b is 512, K, M, G and so on
+// count in powers of 1024, with iB the same and with B in powers of 1000
+fn parse_size(val: &str) -> MyResult<usize> {
+    let split = val.find(|c: char| !c.is_ascii_digit()).unwrap_or(val.len());
+    let (digits, suffix) = val.split_at(split);
+    if digits.is_empty() {
+        return Err(From::from(val));
     }
+    let multiplier = multiplier(suffix).ok_or(val)?;
+    // Only overflow can fail once the digits are known to be there
+    let too_large = || format!("{}: value too large", val);
+    let num: u128 = digits.parse().map_err(|_| too_large())?;
+    num.checked_mul(multiplier)
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(|| From::from(too_large()))
+}
+
+fn multiplier(suffix: &str) -> Option<u128> {
+    if suffix.is_empty() {
+        return Some(1);
+    }
+    if suffix == "b" {
+        return Some(512);
+    }
+    let mut chars = suffix.chars();
+    let power = match chars.next()? {
+        'k' | 'K' => 1,
+        'm' | 'M' => 2,
+        'G' => 3,
+        'T' => 4,
+        'P' => 5,
+        'E' => 6,
+        'Z' => 7,
+        'Y' => 8,
+        'R' => 9,
+        'Q' => 10,
+        _ => return None,
+    };
+    let base: u128 = match chars.as_str() {
+        "" | "iB" => 1024,
+        "B" => 1000,
+        _ => return None,
+    };
+    Some(base.pow(power))
 }
 
 fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
@@ -138,3 +251,77 @@ fn test_parse_positive_int() {
     assert!(res.is_err());
     assert_eq!(res.unwrap_err().to_string(), "0".to_string());
 }
+
+#[test]
+fn test_parse_count() {
+    let res = parse_count("3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), Count::First(3));
+
+    let res = parse_count("-3");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), Count::AllButLast(3));
+
+    let res = parse_count("-0");
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), Count::AllButLast(0));
+
+    let res = parse_count("0");
+    assert!(res.is_err());
+    assert_eq!(res.unwrap_err().to_string(), "0".to_string());
+
+    let res = parse_count("-+3");
+    assert!(res.is_err());
+    assert_eq!(res.unwrap_err().to_string(), "-+3".to_string());
+
+    let res = parse_count("-foo");
+    assert!(res.is_err());
+    assert_eq!(res.unwrap_err().to_string(), "-foo".to_string());
+}
+
+#[test]
+fn test_parse_size() {
+    let table = [
+        ("7", 7),
+        ("2b", 2 * 512),
+        ("2k", 2 * 1024),
+        ("2K", 2 * 1024),
+        ("2KiB", 2 * 1024),
+        ("2kB", 2 * 1000),
+        ("2KB", 2 * 1000),
+        ("2m", 2 * 1024 * 1024),
+        ("2M", 2 * 1024 * 1024),
+        ("2MiB", 2 * 1024 * 1024),
+        ("2MB", 2 * 1000 * 1000),
+        ("2G", 2 << 30),
+        ("2GiB", 2 << 30),
+        ("2GB", 2_000_000_000),
+        ("2T", 2 << 40),
+        ("2TB", 2_000_000_000_000),
+        ("2P", 2 << 50),
+        ("2PB", 2_000_000_000_000_000),
+        ("2E", 2 << 60),
+        ("2EB", 2_000_000_000_000_000_000),
+        ("0K", 0),
+    ];
+    for (val, expected) in table {
+        let res = parse_size(val);
+        assert!(res.is_ok(), "{}", val);
+        assert_eq!(res.unwrap(), expected, "{}", val);
+    }
+
+    for val in ["K", "2B", "2Ki", "2g", "2t", "2bB", "2x", "1.5K", "2KIB"] {
+        let res = parse_size(val);
+        assert!(res.is_err(), "{}", val);
+        assert_eq!(res.unwrap_err().to_string(), val.to_string());
+    }
+
+    for val in ["16E", "1Z", "1Y", "1R", "1Q", "99999999999999999999999"] {
+        let res = parse_size(val);
+        assert!(res.is_err(), "{}", val);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            format!("{}: value too large", val)
+        );
+    }
+}
@@ -1,3 +1,6 @@
+// Some tests pass borrowed arrays and slices where clippy wants values
+#![allow(clippy::needless_borrow, clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
@@ -15,6 +18,7 @@ const ONE: &str = "./tests/inputs/one.txt";
 const TWO: &str = "./tests/inputs/two.txt";
 const THREE: &str = "./tests/inputs/three.txt";
 const TEN: &str = "./tests/inputs/ten.txt";
+const RANDOM: &str = "./tests/inputs/random.bin";
 
 // --------------------------------------------------
 fn random_string() -> String {
@@ -93,35 +97,31 @@ fn skips_bad_file() -> TestResult {
 
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
-    // Extra work here due to lossy UTF
     let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
+    let mut expected = Vec::new();
+    file.read_to_end(&mut expected)?;
 
     Command::cargo_bin(PRG)?
         .args(args)
         .assert()
         .success()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(&expected as &[u8]));
 
     Ok(())
 }
 
 // --------------------------------------------------
 fn run_stdin(args: &[&str], input_file: &str, expected_file: &str) -> TestResult {
-    // Extra work here due to lossy UTF
     let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
+    let mut expected = Vec::new();
+    file.read_to_end(&mut expected)?;
     let input = fs::read_to_string(input_file)?;
 
     Command::cargo_bin(PRG)?
         .write_stdin(input)
         .args(args)
         .assert()
-        .stdout(predicate::eq(&expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(&expected as &[u8]));
 
     Ok(())
 }
@@ -415,3 +415,123 @@ fn multiple_files_c4() -> TestResult {
         "tests/expected/all.c4.out",
     )
 }
+
+// --------------------------------------------------
+#[test]
+fn empty_n_minus2() -> TestResult {
+    run(&[EMPTY, "-n", "-2"], "tests/expected/empty.txt.n-2.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn empty_c_minus3() -> TestResult {
+    run(&[EMPTY, "-c", "-3"], "tests/expected/empty.txt.c-3.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn one_n_minus2() -> TestResult {
+    run(&[ONE, "-n", "-2"], "tests/expected/one.txt.n-2.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn one_c_minus3() -> TestResult {
+    run(&[ONE, "-c", "-3"], "tests/expected/one.txt.c-3.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn two_n_minus2() -> TestResult {
+    run(&[TWO, "-n", "-2"], "tests/expected/two.txt.n-2.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn two_c_minus3() -> TestResult {
+    run(&[TWO, "-c", "-3"], "tests/expected/two.txt.c-3.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn three_n_minus2() -> TestResult {
+    run(&[THREE, "-n", "-2"], "tests/expected/three.txt.n-2.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn three_c_minus3() -> TestResult {
+    run(&[THREE, "-c", "-3"], "tests/expected/three.txt.c-3.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_n_minus2() -> TestResult {
+    run(&[TEN, "-n", "-2"], "tests/expected/ten.txt.n-2.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_c_minus3() -> TestResult {
+    run(&[TEN, "-c", "-3"], "tests/expected/ten.txt.c-3.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_n_minus2_stdin() -> TestResult {
+    run_stdin(&["--lines=-2"], TEN, "tests/expected/ten.txt.n-2.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_c_minus3_stdin() -> TestResult {
+    run_stdin(&["--bytes=-3"], TEN, "tests/expected/ten.txt.c-3.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_n_minus2() -> TestResult {
+    run(
+        &[EMPTY, ONE, TWO, THREE, TEN, "-n", "-2"],
+        "tests/expected/all.n-2.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_c_minus3() -> TestResult {
+    run(
+        &["-c", "-3", EMPTY, ONE, TWO, THREE, TEN],
+        "tests/expected/all.c-3.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_c_1k() -> TestResult {
+    run(&[TEN, "-c", "1K"], "tests/expected/ten.txt.c1K.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn random_c_1k() -> TestResult {
+    run(&[RANDOM, "-c", "1K"], "tests/expected/random.bin.c1K.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn ten_c_minus_1b() -> TestResult {
+    run(&[TEN, "--bytes=-1b"], "tests/expected/ten.txt.c-1b.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_too_many_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "20E", TEN])
+        .assert()
+        .failure()
+        .stderr("illegal byte count -- 20E: value too large\n");
+
+    Ok(())
+}
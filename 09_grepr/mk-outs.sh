@@ -42,6 +42,47 @@ grep -ci nobody tests/inputs/nobody.txt > "$OUT_DIR/nobody.txt.insensitive.count
 grep -c The $DIR/*.txt > "$OUT_DIR/all.the.capitalized.count"
 grep -ci the $DIR/*.txt > "$OUT_DIR/all.the.lowercase.insensitive.count"
 
+# Line numbers and byte offsets
+grep -n the $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.lowercase.line_number"
+grep -b the $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.lowercase.byte_offset"
+grep -nbi the $DIR/*.txt > "$OUT_DIR/all.the.lowercase.insensitive.line_number.byte_offset"
+
+# Context
+grep -A1 the $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.lowercase.after_context"
+grep -B2 -n the $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.lowercase.before_context"
+grep -C1 -vn the $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.lowercase.invert.context"
+grep -C1 -i the $DIR/*.txt > "$OUT_DIR/all.the.lowercase.insensitive.context"
+
+# Only matching and color
+grep -o -nb -i the $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.lowercase.insensitive.only_matching"
+grep -o -C1 The $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.capitalized.only_matching.context"
+grep --color=always -ni the $DIR/*.txt > "$OUT_DIR/all.the.lowercase.insensitive.color"
+grep --color=always -v -C1 -n the $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.lowercase.invert.context.color"
+
+# Multiple and fixed-string patterns
+grep -e fox -e Nobody $DIR/fox.txt $DIR/nobody.txt > "$OUT_DIR/fox.nobody.regexp"
+grep -ci -e the -f tests/patterns/words.txt $DIR/*.txt > "$OUT_DIR/all.the.words.insensitive.count"
+grep -F -f tests/patterns/literals.txt $DIR/fox.txt $DIR/nobody.txt > "$OUT_DIR/fox.nobody.literals.fixed_strings"
+grep -f tests/patterns/literals.txt $DIR/fox.txt $DIR/nobody.txt > "$OUT_DIR/fox.nobody.literals"
+
+# Whole words and lines (grepr treats a CRLF ending as a line ending for -x)
+grep -w the $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.lowercase.word"
+grep -wci the $DIR/*.txt > "$OUT_DIR/all.the.lowercase.insensitive.word.count"
+grep -wv -F -e tell -e you $DIR/nobody.txt > "$OUT_DIR/nobody.txt.tell.you.word.invert"
+grep -x -i $'to.an.admiring.bog!\r' $DIR/nobody.txt > "$OUT_DIR/nobody.txt.bog.line"
+
+# Max count
+grep -m1 -A2 -n The $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.capitalized.max_count.after_context"
+grep -m2 -ci the $DIR/*.txt > "$OUT_DIR/all.the.lowercase.insensitive.max_count.count"
+grep -m1 -vn The $DIR/bustle.txt > "$OUT_DIR/bustle.txt.the.capitalized.max_count.invert"
+
+# File names
+grep -H fox $DIR/fox.txt > "$OUT_DIR/fox.txt.fox.with_filename"
+grep -hn the $DIR/*.txt > "$OUT_DIR/all.the.lowercase.no_filename"
+grep -Zc the $DIR/*.txt > "$OUT_DIR/all.the.lowercase.null.count"
+grep -lZ the $DIR/*.txt > "$OUT_DIR/all.the.lowercase.null.files_with_matches"
+grep -Z -A1 -n house $DIR/bustle.txt $DIR/fox.txt > "$OUT_DIR/bustle.fox.house.null.after_context"
+
 # Recursive, insensitive, count
 grep -cri the tests/inputs > "$OUT_DIR/the.recursive.insensitive.count"
 
@@ -1,124 +1,1624 @@
+use bzip2::bufread::MultiBzDecoder;
 use clap::{Arg, ArgAction, Command};
-use regex::{Regex, RegexBuilder};
+use flate2::bufread::MultiGzDecoder;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use regex::bytes::{Regex, RegexBuilder};
+use serde_json::json;
 use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::{BTreeMap, VecDeque},
     error::Error,
+    fmt,
     fs::{self, File},
-    io::{self, BufRead, BufReader},
+    io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write},
     mem,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Condvar, Mutex,
+    },
+    thread,
 };
 use walkdir::WalkDir;
+use xz2::bufread::XzDecoder;
 
-type MyResult<T> = Result<T, Box<dyn Error>>;
+#[cfg(feature = "pcre")]
+mod pcre;
+#[cfg(feature = "pcre")]
+use pcre::build_pcre;
+
+type MyResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// GNU grep's default GREP_COLORS
+const MATCH_COLOR: &str = "01;31";
+const FILENAME_COLOR: &str = "35";
+const NUMBER_COLOR: &str = "32";
+const SEPARATOR_COLOR: &str = "36";
 
 #[derive(Debug)]
 pub struct Config {
-    pattern: Regex,
+    pattern: Box<dyn Matcher>,
     files: Vec<String>,
     recursive: bool,
     count: bool,
     invert_match: bool,
+    line_number: bool,
+    byte_offset: bool,
+    before_context: Option<usize>,
+    after_context: Option<usize>,
+    only_matching: bool,
+    color: bool,
+    files_with_matches: bool,
+    files_without_match: bool,
+    quiet: bool,
+    max_count: Option<usize>,
+    binary_files: BinaryFiles,
+    filter: FileFilter,
+    threads: usize,
+    unordered: bool,
+    json: bool,
+    replace: Option<Vec<u8>>,
+    in_place: bool,
+    multiline: bool,
+    with_filename: Option<bool>,
+    null: bool,
+    search_zip: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    exclude_dir: GlobSet,
+    ignore_files: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryFiles {
+    Binary,
+    Text,
+    WithoutMatch,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SearchOptions {
+    invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    max_count: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    None,
+    Word,
+    Line,
+}
+
+// What a search needs from a regex engine, with spans as byte ranges. An
+// engine that can give up partway through a search returns that as an error.
+trait Matcher: fmt::Debug + Send + Sync {
+    fn is_match(&self, text: &[u8]) -> MyResult<bool>;
+    fn find_spans(&self, text: &[u8]) -> MyResult<Vec<Range<usize>>>;
+    // Each match along with the template expanded from its capture groups
+    fn expand_matches(
+        &self,
+        text: &[u8],
+        template: &[u8],
+    ) -> MyResult<Vec<(Range<usize>, Vec<u8>)>>;
+}
+
+impl Matcher for Regex {
+    fn is_match(&self, text: &[u8]) -> MyResult<bool> {
+        Ok(Regex::is_match(self, text))
+    }
+
+    fn find_spans(&self, text: &[u8]) -> MyResult<Vec<Range<usize>>> {
+        Ok(self.find_iter(text).map(|m| m.range()).collect())
+    }
+
+    fn expand_matches(
+        &self,
+        text: &[u8],
+        template: &[u8],
+    ) -> MyResult<Vec<(Range<usize>, Vec<u8>)>> {
+        Ok(self
+            .captures_iter(text)
+            .map(|caps| {
+                let mut replaced = vec![];
+                caps.expand(template, &mut replaced);
+                (caps.get(0).unwrap().range(), replaced)
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct FileStatus {
+    searched: bool,
+    selected_lines: usize,
+    failed: bool,
+}
+
+#[derive(Debug)]
+pub enum FindError {
+    IsDirectory(String),
+    Io(String, io::Error),
+}
+
+impl FindError {
+    fn path(&self) -> &str {
+        match self {
+            FindError::IsDirectory(path) | FindError::Io(path, _) => path,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            FindError::IsDirectory(_) => "is a directory".to_string(),
+            FindError::Io(_, e) => e.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FindError::IsDirectory(path) => write!(f, "{} is a directory", path),
+            FindError::Io(path, e) => write!(f, "{}: {}", path, e),
+        }
+    }
+}
+
+impl Error for FindError {}
+
+#[derive(Debug, PartialEq)]
+pub enum LineKind {
+    Match,
+    Context,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MatchedLine {
+    kind: LineKind,
+    line_num: usize,
+    byte_offset: usize,
+    line: Vec<u8>,
+}
+
+pub fn get_args() -> MyResult<Config> {
+    let matches = Command::new("grepr")
+        .version("0.1.0")
+        .author("marvinhsu")
+        .about("Rust grep")
+        // -h is --no-filename, as in grep
+        .disable_help_flag(true)
+        .arg(
+            Arg::new("help")
+                .long("help")
+                .help("Print help")
+                .action(ArgAction::Help),
+        )
+        .arg(
+            Arg::new("pattern")
+                .value_name("PATTERN")
+                .help("Search pattern")
+                .required_unless_present_any(["regexp", "pattern_file"]),
+        )
+        .arg(
+            Arg::new("files")
+                .value_name("FILE")
+                .help("Input file(s) [default: -]")
+                .num_args(0..)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("regexp")
+                .short('e')
+                .long("regexp")
+                .value_name("PATTERN")
+                .help("Use PATTERN for matching")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("pattern_file")
+                .short('f')
+                .long("file")
+                .value_name("FILE")
+                .help("Take patterns from FILE")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("fixed_strings")
+                .short('F')
+                .long("fixed-strings")
+                .help("Interpret patterns as fixed strings")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("pcre")
+                .short('P')
+                .long("pcre")
+                .help("Use a backtracking engine with lookaround and backreferences")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("word_regexp")
+                .short('w')
+                .long("word-regexp")
+                .help("Match only whole words")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("line_regexp")
+                .short('x')
+                .long("line-regexp")
+                .help("Match only whole lines")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("multiline")
+                .short('U')
+                .long("multiline")
+                .help("Let matches span lines; use (?s) so . matches newlines")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("count")
+                .short('c')
+                .long("count")
+                .help("Count occurrences")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("insensitive")
+                .short('i')
+                .long("insensitive")
+                .help("Case-insensitive")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("invert")
+                .short('v')
+                .long("invert-match")
+                .help("Invert match")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("recursive")
+                .short('r')
+                .long("recursive")
+                .help("Recursive search")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("line_number")
+                .short('n')
+                .long("line-number")
+                .help("Print line numbers")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("byte_offset")
+                .short('b')
+                .long("byte-offset")
+                .help("Print byte offsets")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("with_filename")
+                .short('H')
+                .long("with-filename")
+                .help("Print the file name for each match")
+                .overrides_with("no_filename")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no_filename")
+                .short('h')
+                .long("no-filename")
+                .help("Never print file names")
+                .overrides_with("with_filename")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("null")
+                .short('Z')
+                .long("null")
+                .help("End file names with a NUL byte")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("after_context")
+                .short('A')
+                .long("after-context")
+                .value_name("NUM")
+                .help("Print NUM lines of trailing context"),
+        )
+        .arg(
+            Arg::new("before_context")
+                .short('B')
+                .long("before-context")
+                .value_name("NUM")
+                .help("Print NUM lines of leading context"),
+        )
+        .arg(
+            Arg::new("context")
+                .short('C')
+                .long("context")
+                .value_name("NUM")
+                .help("Print NUM lines of output context"),
+        )
+        .arg(
+            Arg::new("only_matching")
+                .short('o')
+                .long("only-matching")
+                .help("Print only the matched parts of a line")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .visible_alias("colour")
+                .value_name("WHEN")
+                .help("Highlight matches")
+                .value_parser(["auto", "always", "never"])
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_value("never")
+                .default_missing_value("auto"),
+        )
+        .arg(
+            Arg::new("files_with_matches")
+                .short('l')
+                .long("files-with-matches")
+                .help("Print only names of files with matches")
+                .overrides_with("files_without_match")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("files_without_match")
+                .short('L')
+                .long("files-without-match")
+                .help("Print only names of files without matches")
+                .overrides_with("files_with_matches")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .visible_alias("silent")
+                .help("Suppress all normal output")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max_count")
+                .short('m')
+                .long("max-count")
+                .value_name("NUM")
+                .help("Stop after NUM selected lines per file"),
+        )
+        .arg(
+            Arg::new("text")
+                .short('a')
+                .long("text")
+                .help("Process a binary file as if it were text")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("binary_files")
+                .long("binary-files")
+                .value_name("TYPE")
+                .help("Assume that binary files are TYPE")
+                .value_parser(["binary", "text", "without-match"])
+                .default_value("binary"),
+        )
+        .arg(
+            Arg::new("search_zip")
+                .short('z')
+                .long("search-zip")
+                .help("Search inside gzip, bzip2, xz and zstd compressed files")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("include")
+                .long("include")
+                .value_name("GLOB")
+                .help("Search only files that match GLOB")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .value_name("GLOB")
+                .help("Skip files that match GLOB")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("exclude_dir")
+                .long("exclude-dir")
+                .value_name("GLOB")
+                .help("Skip directories that match GLOB")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("gitignore")
+                .long("gitignore")
+                .help("Honor .gitignore and .ignore files and skip hidden files")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('j')
+                .long("threads")
+                .value_name("NUM")
+                .help("Search NUM files at a time [default: number of CPUs]"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print results as JSON Lines")
+                .conflicts_with_all([
+                    "count",
+                    "only_matching",
+                    "files_with_matches",
+                    "files_without_match",
+                    "quiet",
+                ])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("replace")
+                .long("replace")
+                .value_name("TEMPLATE")
+                .help("Print matches replaced by TEMPLATE, expanding $1 and ${name}")
+                .conflicts_with("json"),
+        )
+        .arg(
+            Arg::new("in_place")
+                .long("in-place")
+                .help("Write the replacements back to the files instead of printing them")
+                .requires("replace")
+                .conflicts_with_all([
+                    "search_zip",
+                    "invert",
+                    "count",
+                    "only_matching",
+                    "files_with_matches",
+                    "files_without_match",
+                    "quiet",
+                ])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("unordered")
+                .long("unordered")
+                .help("Print each file as soon as it is searched")
+                .action(ArgAction::SetTrue),
+        )
+        .get_matches();
+
+    let mut files: Vec<String> = matches
+        .get_many::<String>("files")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    // With -e or -f the first positional argument is a file, not a pattern
+    let mut patterns = vec![];
+    if matches.contains_id("regexp") || matches.contains_id("pattern_file") {
+        if let Some(file) = matches.get_one::<String>("pattern") {
+            files.insert(0, file.clone());
+        }
+        if let Some(regexps) = matches.get_many::<String>("regexp") {
+            patterns.extend(regexps.cloned());
+        }
+        for filename in matches
+            .get_many::<String>("pattern_file")
+            .unwrap_or_default()
+        {
+            patterns.extend(read_patterns(filename)?);
+        }
+    } else {
+        patterns.push(matches.get_one::<String>("pattern").unwrap().clone());
+    }
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let anchor = if matches.get_flag("line_regexp") {
+        Anchor::Line
+    } else if matches.get_flag("word_regexp") {
+        Anchor::Word
+    } else {
+        Anchor::None
+    };
+    let fixed_strings = matches.get_flag("fixed_strings");
+    let insensitive = matches.get_flag("insensitive");
+    let multiline = matches.get_flag("multiline");
+    let pattern: Box<dyn Matcher> = if matches.get_flag("pcre") {
+        build_pcre(&patterns, fixed_strings, insensitive, anchor, multiline)?
+    } else {
+        Box::new(build_pattern(
+            &patterns,
+            fixed_strings,
+            insensitive,
+            anchor,
+            multiline,
+        )?)
+    };
+
+    let parse_context = |name: &str| {
+        matches
+            .get_one::<String>(name)
+            .map(|n| parse_num(n))
+            .transpose()
+            .map_err(|e| format!("{}: invalid context length argument", e))
+    };
+    let context = parse_context("context")?;
+    let before_context = parse_context("before_context")?.or(context);
+    let after_context = parse_context("after_context")?.or(context);
+
+    let globs = |name: &str| {
+        build_globs(
+            matches
+                .get_many::<String>(name)
+                .unwrap_or_default()
+                .map(String::as_str),
+        )
+    };
+    let filter = FileFilter {
+        include: matches
+            .contains_id("include")
+            .then(|| globs("include"))
+            .transpose()?,
+        exclude: globs("exclude")?,
+        exclude_dir: globs("exclude_dir")?,
+        ignore_files: matches.get_flag("gitignore"),
+    };
+
+    let threads = match matches.get_one::<String>("threads") {
+        Some(n) => match parse_num(n) {
+            Ok(n) if n > 0 => n,
+            _ => return Err(From::from(format!("{}: invalid thread count", n))),
+        },
+        None => sys_info::cpu_num().map_or(1, |n| n as usize),
+    };
+
+    let max_count = matches
+        .get_one::<String>("max_count")
+        .map(|n| parse_num(n))
+        .transpose()
+        .map_err(|e| format!("{}: invalid max count", e))?;
+
+    Ok(Config {
+        pattern,
+        files,
+        recursive: matches.get_flag("recursive"),
+        count: matches.get_flag("count"),
+        invert_match: matches.get_flag("invert"),
+        line_number: matches.get_flag("line_number"),
+        byte_offset: matches.get_flag("byte_offset"),
+        before_context,
+        after_context,
+        only_matching: matches.get_flag("only_matching"),
+        color: match matches.get_one::<String>("color").unwrap().as_str() {
+            "always" => true,
+            "auto" => io::stdout().is_terminal(),
+            _ => false,
+        },
+        files_with_matches: matches.get_flag("files_with_matches"),
+        files_without_match: matches.get_flag("files_without_match"),
+        quiet: matches.get_flag("quiet"),
+        max_count,
+        binary_files: if matches.get_flag("text") {
+            BinaryFiles::Text
+        } else {
+            match matches.get_one::<String>("binary_files").unwrap().as_str() {
+                "text" => BinaryFiles::Text,
+                "without-match" => BinaryFiles::WithoutMatch,
+                _ => BinaryFiles::Binary,
+            }
+        },
+        filter,
+        threads,
+        unordered: matches.get_flag("unordered"),
+        json: matches.get_flag("json"),
+        replace: matches
+            .get_one::<String>("replace")
+            .map(|t| t.as_bytes().to_vec()),
+        in_place: matches.get_flag("in_place"),
+        multiline,
+        with_filename: if matches.get_flag("with_filename") {
+            Some(true)
+        } else if matches.get_flag("no_filename") {
+            Some(false)
+        } else {
+            None
+        },
+        null: matches.get_flag("null"),
+        search_zip: matches.get_flag("search_zip"),
+    })
+}
+
+// Returns grep's exit status: 0 if a line was selected, 1 if none was and 2 on error
+pub fn run(config: Config) -> MyResult<i32> {
+    let entries = find_files(&config.files, config.recursive, &config.filter);
+    let show_filename = config.with_filename.unwrap_or(entries.len() > 1);
+    let threads = config.threads.min(entries.len());
+    let stdout = io::stdout();
+    // Only flush every line when someone is watching the output
+    let mut out: Box<dyn Write> = if stdout.is_terminal() {
+        Box::new(stdout.lock())
+    } else {
+        Box::new(BufWriter::new(stdout.lock()))
+    };
+    // Files whose lines are printed are separated like any other group
+    let listing = config.quiet || config.files_with_matches || config.files_without_match;
+    let separate_files = (config.before_context.is_some() || config.after_context.is_some())
+        && !config.count
+        && !listing
+        && !config.json;
+    let separator = format!("{}\n", paint("--", SEPARATOR_COLOR, config.color)).into_bytes();
+    let mut printed_lines = false;
+    let mut totals = Totals::default();
+
+    // Records the outcome of each file in output order and says whether to go on
+    let mut done = |status: FileStatus| {
+        totals.add(status);
+        // Nothing more can change the outcome of a quiet search
+        !(status.selected_lines > 0 && config.quiet)
+    };
+
+    let result = if threads > 1 {
+        search_parallel(&config, &entries, show_filename, threads, |write_file| {
+            let leading = (separate_files && printed_lines).then_some(separator.as_slice());
+            let mut file_out = SeparatedWriter::new(&mut out, leading);
+            let status = write_file(&mut file_out)?;
+            printed_lines |= file_out.written;
+            Ok(done(status))
+        })
+    } else {
+        entries.iter().try_for_each(|entry| {
+            let leading = (separate_files && printed_lines).then_some(separator.as_slice());
+            let mut file_out = SeparatedWriter::new(&mut out, leading);
+            let status = search_entry(&config, entry, show_filename, &mut file_out)?;
+            printed_lines |= file_out.written;
+            if done(status) {
+                Ok(())
+            } else {
+                Err(io::Error::from(io::ErrorKind::Interrupted))
+            }
+        })
+    };
+
+    let result = result.and_then(|_| {
+        if config.json {
+            write_json(&mut out, totals.summary())?;
+        }
+        out.flush()
+    });
+    match result {
+        Ok(()) => {}
+        // The reader went away, as with `grepr pattern file | head`
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(0),
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(e) => return Err(From::from(e)),
+    }
+
+    let selected = totals.searches_with_match > 0;
+    if config.quiet && selected {
+        return Ok(0);
+    }
+    Ok(match (totals.failed, selected) {
+        (true, _) => 2,
+        (false, true) => 0,
+        (false, false) => 1,
+    })
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    searches: usize,
+    searches_with_match: usize,
+    selected_lines: usize,
+    failed: bool,
+}
+
+impl Totals {
+    fn add(&mut self, status: FileStatus) {
+        self.searches += usize::from(status.searched);
+        self.searches_with_match += usize::from(status.selected_lines > 0);
+        self.selected_lines += status.selected_lines;
+        self.failed |= status.failed;
+    }
+
+    fn summary(&self) -> serde_json::Value {
+        json!({
+            "type": "summary",
+            "data": {
+                "searches": self.searches,
+                "searches_with_match": self.searches_with_match,
+                "matched_lines": self.selected_lines,
+            },
+        })
+    }
+}
+
+// Searches files on a pool of worker threads. Files are handed to `emit` in
+// the order of `entries`, or in the order they start producing output when
+// unordered, and `emit` writes each out in full before the next so its output
+// stays contiguous. `emit` returns false to stop.
+fn search_parallel<F>(
+    config: &Config,
+    entries: &[Result<String, FindError>],
+    show_filename: bool,
+    threads: usize,
+    mut emit: F,
+) -> io::Result<()>
+where
+    F: FnMut(&mut dyn FnMut(&mut dyn Write) -> io::Result<FileStatus>) -> io::Result<bool>,
+{
+    let next_entry = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    // The file being written out, whose output goes through in small chunks
+    let current = AtomicUsize::new(0);
+    // How many files have been written out, which limits how far ahead the
+    // workers may go
+    let emitted = (Mutex::new(0), Condvar::new());
+    let (ready_tx, ready_rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let ready_tx = ready_tx.clone();
+            let (next_entry, stop, current, emitted) = (&next_entry, &stop, &current, &emitted);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let i = next_entry.fetch_add(1, Ordering::Relaxed);
+                    let Some(entry) = entries.get(i) else {
+                        break;
+                    };
+                    let (lock, turn) = emitted;
+                    let mut done = lock.lock().unwrap();
+                    while i >= *done + threads * LOOKAHEAD && !stop.load(Ordering::Relaxed) {
+                        done = turn.wait(done).unwrap();
+                    }
+                    drop(done);
+
+                    let mut out = ChunkWriter::new(i, &ready_tx, current);
+                    // Only a reader that has gone away makes writing fail
+                    let status =
+                        search_entry(config, entry, show_filename, &mut out).unwrap_or_default();
+                    if out.finish(status).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(ready_tx);
+
+        // Files that have output ready ahead of the one being written wait here
+        let mut waiting = BTreeMap::new();
+        let mut next_out = 0;
+        let mut result = Ok(());
+        'files: loop {
+            let (i, chunks) = if config.unordered {
+                match ready_rx.recv() {
+                    Ok(ready) => ready,
+                    Err(_) => break,
+                }
+            } else {
+                loop {
+                    if let Some(chunks) = waiting.remove(&next_out) {
+                        break (next_out, chunks);
+                    }
+                    match ready_rx.recv() {
+                        Ok((i, chunks)) => waiting.insert(i, chunks),
+                        Err(_) => break 'files,
+                    };
+                }
+            };
+            next_out += 1;
+            current.store(i, Ordering::Relaxed);
+
+            let emitted_file = emit(&mut |out| {
+                for chunk in &chunks {
+                    match chunk {
+                        Chunk::Bytes(bytes) => out.write_all(&bytes)?,
+                        Chunk::Done(status) => return Ok(status),
+                    }
+                }
+                Ok(FileStatus::default())
+            });
+            let (lock, turn) = &emitted;
+            *lock.lock().unwrap() += 1;
+            turn.notify_all();
+            match emitted_file {
+                Ok(true) => {}
+                stopped => {
+                    result = stopped.map(|_| ());
+                    break;
+                }
+            }
+        }
+        // Workers blocked on a full channel or on the window see the
+        // receivers go away or the stop flag
+        stop.store(true, Ordering::Relaxed);
+        emitted.1.notify_all();
+        drop(waiting);
+        drop(ready_rx);
+        result
+    })
+}
+
+// Chunks of output each worker may have waiting, and how many files ahead
+// of the output per thread the workers may search. The file being written
+// out sends smaller chunks, about what the output buffers at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+const STREAM_CHUNK_SIZE: usize = 8 * 1024;
+const CHUNKS_IN_FLIGHT: usize = 4;
+const LOOKAHEAD: usize = 2;
+
+enum Chunk {
+    Bytes(Vec<u8>),
+    Done(FileStatus),
+}
+
+// Passes a worker's output on in chunks over a bounded channel, so a file
+// waiting for its turn holds only a few chunks in memory. The channel is
+// announced with the first chunk.
+struct ChunkWriter<'a> {
+    index: usize,
+    buf: Vec<u8>,
+    tx: SyncSender<Chunk>,
+    rx: Option<Receiver<Chunk>>,
+    ready: &'a Sender<(usize, Receiver<Chunk>)>,
+    current: &'a AtomicUsize,
+}
+
+impl<'a> ChunkWriter<'a> {
+    fn new(
+        index: usize,
+        ready: &'a Sender<(usize, Receiver<Chunk>)>,
+        current: &'a AtomicUsize,
+    ) -> Self {
+        let (tx, rx) = mpsc::sync_channel(CHUNKS_IN_FLIGHT);
+        ChunkWriter {
+            index,
+            buf: vec![],
+            tx,
+            rx: Some(rx),
+            ready,
+            current,
+        }
+    }
+
+    fn send(&mut self, chunk: Chunk) -> io::Result<()> {
+        // The receiving end only goes away once the search has stopped
+        let gone = || io::Error::from(io::ErrorKind::BrokenPipe);
+        if let Some(rx) = self.rx.take() {
+            self.ready.send((self.index, rx)).map_err(|_| gone())?;
+        }
+        self.tx.send(chunk).map_err(|_| gone())
+    }
+
+    fn finish(mut self, status: FileStatus) -> io::Result<()> {
+        self.flush()?;
+        self.send(Chunk::Done(status))
+    }
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        let streaming = self.current.load(Ordering::Relaxed) == self.index;
+        let size = if streaming {
+            STREAM_CHUNK_SIZE
+        } else {
+            CHUNK_SIZE
+        };
+        if self.buf.len() >= size {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Chunk::Bytes(mem::take(&mut self.buf));
+        self.send(chunk)
+    }
+}
+
+// Writes `leading` before the first bytes that go through, if any do
+struct SeparatedWriter<'a, W: Write + ?Sized> {
+    inner: &'a mut W,
+    leading: Option<&'a [u8]>,
+    written: bool,
+}
+
+impl<'a, W: Write + ?Sized> SeparatedWriter<'a, W> {
+    fn new(inner: &'a mut W, leading: Option<&'a [u8]>) -> Self {
+        SeparatedWriter {
+            inner,
+            leading,
+            written: false,
+        }
+    }
+}
+
+impl<W: Write + ?Sized> Write for SeparatedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(leading) = self.leading.take() {
+            self.inner.write_all(leading)?;
+        }
+        self.written = true;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Reports any error for one entry of find_files and searches it. Only a
+// failure to write the output is returned as an error.
+fn search_entry(
+    config: &Config,
+    entry: &Result<String, FindError>,
+    show_filename: bool,
+    out: &mut dyn Write,
+) -> io::Result<FileStatus> {
+    let filename = match entry {
+        Ok(filename) => filename,
+        Err(e) => return report_error(config, out, e.path(), &e.message(), &e.to_string()),
+    };
+    let opened = match open(filename) {
+        Ok(file) if config.search_zip => decompress(file),
+        opened => opened,
+    };
+    let file = match opened {
+        Ok(file) => file,
+        Err(e) => {
+            let message = e.to_string();
+            let text = format!("{}: {}", filename, message);
+            return report_error(config, out, filename, &message, &text);
+        }
+    };
+    let result = match &config.replace {
+        Some(template) if config.in_place => rewrite_file(config, filename, template),
+        _ => search_file(config, file, filename, show_filename, out),
+    };
+    match result {
+        Ok(selected_lines) => Ok(FileStatus {
+            searched: true,
+            selected_lines,
+            failed: false,
+        }),
+        Err(e) if is_broken_pipe(e.as_ref()) => Err(io::ErrorKind::BrokenPipe.into()),
+        Err(e) => {
+            let message = e.to_string();
+            let text = format!("{}: {}", filename, message);
+            report_error(config, out, filename, &message, &text)
+        }
+    }
+}
+
+// Errors go to stderr, or into the output as records in JSON mode
+fn report_error(
+    config: &Config,
+    out: &mut dyn Write,
+    path: &str,
+    message: &str,
+    text: &str,
+) -> io::Result<FileStatus> {
+    if config.json {
+        write_json(
+            out,
+            json!({"type": "error", "data": {"path": path, "message": message}}),
+        )?;
+    } else {
+        eprintln!("{}", text);
+    }
+    Ok(FileStatus {
+        failed: true,
+        ..Default::default()
+    })
+}
+
+fn write_json(out: &mut dyn Write, record: serde_json::Value) -> io::Result<()> {
+    writeln!(out, "{}", record)
+}
+
+fn is_broken_pipe(e: &(dyn Error + 'static)) -> bool {
+    matches!(e.downcast_ref::<io::Error>(), Some(e) if e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+fn search_file(
+    config: &Config,
+    file: Box<dyn BufRead>,
+    filename: &str,
+    show_filename: bool,
+    out: &mut dyn Write,
+) -> MyResult<usize> {
+    let label = match filename {
+        "-" => "(standard input)",
+        _ => filename,
+    };
+    let fname = show_filename.then_some(label);
+    let listing = config.quiet || config.files_with_matches || config.files_without_match;
+    let nul_seen = Cell::new(false);
+    let mut file = NulDetector::new(file, &nul_seen);
+    let mut binary = config.binary_files != BinaryFiles::Text && is_binary(&mut file)?;
+    let printing = !listing && !config.count && !binary;
+
+    let options = SearchOptions {
+        invert_match: config.invert_match,
+        before_context: config.before_context.filter(|_| printing).unwrap_or(0),
+        after_context: config.after_context.filter(|_| printing).unwrap_or(0),
+        // Listing and quiet modes only need to know whether anything matches
+        max_count: if listing { Some(1) } else { config.max_count },
+    };
+
+    if config.json {
+        write_json(out, json!({"type": "begin", "data": {"path": label}}))?;
+    }
+
+    let mut last_printed = None;
+    let mut binary_match = false;
+    let count = if binary && config.binary_files == BinaryFiles::WithoutMatch {
+        0
+    } else {
+        let sink = |matched: MatchedLine| {
+            if listing || config.count {
+                return Ok(true);
+            }
+            // Stop printing once a NUL shows up anywhere in what has been read,
+            // matching line or not, and report the next match instead
+            if !binary && config.binary_files != BinaryFiles::Text {
+                binary = nul_seen.get();
+            }
+            if binary {
+                binary_match = matched.kind == LineKind::Match;
+                return Ok(!binary_match);
+            }
+            if config.json {
+                write_json(out, json_line(config.pattern.as_ref(), label, &matched)?)?;
+            } else {
+                print_line(out, config, fname, &matched, &mut last_printed)?;
+            }
+            Ok(true)
+        };
+        if config.multiline {
+            let mut buffer = vec![];
+            file.read_to_end(&mut buffer)?;
+            find_multiline(&buffer, config.pattern.as_ref(), &options, sink)?
+        } else {
+            find_lines(file, config.pattern.as_ref(), &options, sink)?
+        }
+    };
+
+    if config.json {
+        let stats = json!({"path": label, "matched_lines": count, "binary": binary_match});
+        write_json(out, json!({"type": "end", "data": stats}))?;
+    } else if listing {
+        let listed = if count > 0 {
+            config.files_with_matches
+        } else {
+            config.files_without_match
+        };
+        if listed && !config.quiet {
+            let end = if config.null { '\0' } else { '\n' };
+            write!(out, "{}{}", paint(label, FILENAME_COLOR, config.color), end)?;
+        }
+    } else if config.count {
+        writeln!(out, "{}{}", format_prefix(config, fname, ':', None), count)?;
+    } else if binary_match {
+        writeln!(out, "Binary file {} matches", label)?;
+    }
+    Ok(count)
+}
+
+// Replaces the matches in the selected lines of a file and renames the new
+// contents over it, so readers see either the old file or the new one
+fn rewrite_file(config: &Config, filename: &str, template: &[u8]) -> MyResult<usize> {
+    if filename == "-" {
+        return Err(From::from("standard input cannot be rewritten in place"));
+    }
+    let content = fs::read(filename)?;
+    if config.binary_files != BinaryFiles::Text && content.contains(&0) {
+        return Ok(0);
+    }
+
+    let limit = config.max_count.unwrap_or(usize::MAX);
+    let (rewritten, count) = if config.multiline {
+        let mut replacements = config.pattern.expand_matches(&content, template)?;
+        replacements.truncate(limit);
+        let count = replacements.len();
+        (splice(&content, replacements, false), count)
+    } else {
+        let mut rewritten = Vec::with_capacity(content.len());
+        let mut count = 0;
+        for line in content.split_inclusive(|&b| b == b'\n') {
+            if count < limit && config.pattern.is_match(trim_line_ending(line))? {
+                rewritten.extend(replace_matches(
+                    config.pattern.as_ref(),
+                    line,
+                    template,
+                    false,
+                )?);
+                count += 1;
+            } else {
+                rewritten.extend_from_slice(line);
+            }
+        }
+        (rewritten, count)
+    };
+
+    if count > 0 {
+        let path = Path::new(filename);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+        temp.write_all(&rewritten)?;
+        temp.as_file()
+            .set_permissions(fs::metadata(path)?.permissions())?;
+        temp.persist(path)?;
+    }
+    Ok(count)
+}
+
+// Notes whether a NUL has gone by in any buffer read from the file, so one
+// in a line that is never printed still counts
+struct NulDetector<'a, R> {
+    inner: R,
+    seen: &'a Cell<bool>,
+    // How much of the current buffer has been looked at already
+    scanned: usize,
+}
+
+impl<'a, R: BufRead> NulDetector<'a, R> {
+    fn new(inner: R, seen: &'a Cell<bool>) -> Self {
+        NulDetector {
+            inner,
+            seen,
+            scanned: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for NulDetector<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if buf[..n].contains(&0) {
+            self.seen.set(true);
+        }
+        self.scanned = self.scanned.saturating_sub(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for NulDetector<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let buf = self.inner.fill_buf()?;
+        if buf[self.scanned.min(buf.len())..].contains(&0) {
+            self.seen.set(true);
+        }
+        self.scanned = buf.len();
+        Ok(buf)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.scanned = self.scanned.saturating_sub(amt);
+        self.inner.consume(amt)
+    }
+}
+
+// Like GNU grep, a NUL byte in the first buffer marks a file as binary
+fn is_binary(file: &mut dyn BufRead) -> io::Result<bool> {
+    Ok(file.fill_buf()?.contains(&0))
+}
+
+fn print_line(
+    out: &mut dyn Write,
+    config: &Config,
+    fname: Option<&str>,
+    matched: &MatchedLine,
+    last_printed: &mut Option<usize>,
+) -> MyResult<()> {
+    // A new group follows a gap in the line numbers
+    let new_group = last_printed.is_some_and(|prev| matched.line_num != prev + 1);
+    let use_separator = config.before_context.is_some() || config.after_context.is_some();
+    if use_separator && new_group {
+        writeln!(out, "{}", paint("--", SEPARATOR_COLOR, config.color))?;
+    }
+    // In multiline mode one record can hold several lines
+    let text = trim_line_ending(&matched.line);
+    let line_breaks = |end: usize| text[..end].iter().filter(|&&b| b == b'\n').count();
+    *last_printed = Some(matched.line_num + line_breaks(text.len()));
+
+    if config.only_matching {
+        // Context lines are never shown, only the separators they imply
+        if matched.kind == LineKind::Context {
+            return Ok(());
+        }
+        let parts = match &config.replace {
+            Some(template) => config.pattern.expand_matches(text, template)?,
+            None => config
+                .pattern
+                .find_spans(text)?
+                .into_iter()
+                .map(|span| (span.clone(), text[span].to_vec()))
+                .collect(),
+        };
+        for (span, part) in parts {
+            if span.is_empty() {
+                continue;
+            }
+            let line_num = matched.line_num + line_breaks(span.start);
+            let numbers = (line_num, matched.byte_offset + span.start);
+            write!(out, "{}", format_prefix(config, fname, ':', Some(numbers)))?;
+            out.write_all(&paint_bytes(&part, MATCH_COLOR, config.color))?;
+            writeln!(out)?;
+        }
+    } else {
+        let sep = match matched.kind {
+            LineKind::Match => ':',
+            LineKind::Context => '-',
+        };
+        let printed = match &config.replace {
+            Some(template) if matched.kind == LineKind::Match => replace_matches(
+                config.pattern.as_ref(),
+                &matched.line,
+                template,
+                config.color,
+            )?,
+            _ => highlight(config.pattern.as_ref(), &matched.line, config.color)?,
+        };
+        // Each line gets its own prefix, numbered from the lines that were read
+        let mut offsets = matched.line.split_inclusive(|&b| b == b'\n').scan(
+            matched.byte_offset,
+            |offset, line| {
+                let start = *offset;
+                *offset += line.len();
+                Some(start)
+            },
+        );
+        let mut byte_offset = matched.byte_offset;
+        for (i, line) in printed.split_inclusive(|&b| b == b'\n').enumerate() {
+            byte_offset = offsets.next().unwrap_or(byte_offset);
+            let numbers = (matched.line_num + i, byte_offset);
+            write!(out, "{}", format_prefix(config, fname, sep, Some(numbers)))?;
+            out.write_all(line)?;
+        }
+    }
+    Ok(())
+}
+
+// Describes a printed line and the spans of each match in it
+fn json_line(
+    pattern: &dyn Matcher,
+    path: &str,
+    matched: &MatchedLine,
+) -> MyResult<serde_json::Value> {
+    let line = trim_line_ending(&matched.line);
+    let text = String::from_utf8_lossy(line);
+    // Spans index the lossy text, where each bad sequence is three bytes of U+FFFD
+    let offset = |pos: usize| match text {
+        Cow::Borrowed(_) => pos,
+        Cow::Owned(_) => String::from_utf8_lossy(&line[..pos]).len(),
+    };
+    let (kind, submatches) = match matched.kind {
+        LineKind::Match => {
+            let spans: Vec<_> = pattern
+                .find_spans(line)?
+                .into_iter()
+                .filter(|span| !span.is_empty())
+                .map(|span| {
+                    json!({
+                        "text": String::from_utf8_lossy(&line[span.clone()]),
+                        "start": offset(span.start),
+                        "end": offset(span.end),
+                    })
+                })
+                .collect();
+            ("match", spans)
+        }
+        LineKind::Context => ("context", vec![]),
+    };
+    Ok(json!({
+        "type": kind,
+        "data": {
+            "path": path,
+            "line_number": matched.line_num,
+            "byte_offset": matched.byte_offset,
+            "text": text,
+            "submatches": submatches,
+        },
+    }))
+}
+
+fn format_prefix(
+    config: &Config,
+    fname: Option<&str>,
+    sep: char,
+    numbers: Option<(usize, usize)>,
+) -> String {
+    let sep = paint(&sep.to_string(), SEPARATOR_COLOR, config.color);
+    let mut prefix = String::new();
+    if let Some(fname) = fname {
+        prefix += &paint(fname, FILENAME_COLOR, config.color);
+        // A NUL cannot appear in a path, so -Z output splits unambiguously
+        if config.null {
+            prefix.push('\0');
+        } else {
+            prefix += &sep;
+        }
+    }
+    if let Some((line_num, byte_offset)) = numbers {
+        if config.line_number {
+            prefix += &paint(&line_num.to_string(), NUMBER_COLOR, config.color);
+            prefix += &sep;
+        }
+        if config.byte_offset {
+            prefix += &paint(&byte_offset.to_string(), NUMBER_COLOR, config.color);
+            prefix += &sep;
+        }
+    }
+    prefix
+}
+
+fn paint(text: &str, color: &str, enabled: bool) -> String {
+    String::from_utf8_lossy(&paint_bytes(text.as_bytes(), color, enabled)).into_owned()
+}
+
+fn paint_bytes(text: &[u8], color: &str, enabled: bool) -> Vec<u8> {
+    if !enabled {
+        return text.to_vec();
+    }
+    // Color each line on its own so a prefix can go between them
+    let mut painted = vec![];
+    for line in text.split_inclusive(|&b| b == b'\n') {
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        if !content.is_empty() {
+            painted.extend(format!("\x1b[{}m\x1b[K", color).into_bytes());
+            painted.extend_from_slice(content);
+            painted.extend_from_slice(b"\x1b[m\x1b[K");
+        }
+        painted.extend_from_slice(&line[content.len()..]);
+    }
+    painted
 }
 
-pub fn get_args() -> MyResult<Config> {
-    let matches = Command::new("grepr")
-        .version("0.1.0")
-        .author("marvinhsu")
-        .about("Rust grep")
-        .arg(
-            Arg::new("pattern")
-                .value_name("PATTERN")
-                .help("Search pattern")
-                .required(true),
-        )
-        .arg(
-            Arg::new("files")
-                .value_name("FILE")
-                .help("Input file(s)")
-                .default_value("-")
-                .num_args(0..)
-                .action(ArgAction::Append),
-        )
-        .arg(
-            Arg::new("count")
-                .short('c')
-                .long("count")
-                .help("Count occurrences")
-                .action(ArgAction::SetTrue),
-        )
-        .arg(
-            Arg::new("insensitive")
-                .short('i')
-                .long("insensitive")
-                .help("Case-insensitive")
-                .action(ArgAction::SetTrue),
-        )
-        .arg(
-            Arg::new("invert")
-                .short('v')
-                .long("invert-match")
-                .help("Invert match")
-                .action(ArgAction::SetTrue),
-        )
-        .arg(
-            Arg::new("recursive")
-                .short('r')
-                .long("recursive")
-                .help("Recursive search")
-                .action(ArgAction::SetTrue),
-        )
-        .get_matches();
+fn highlight(pattern: &dyn Matcher, line: &[u8], enabled: bool) -> MyResult<Vec<u8>> {
+    if !enabled {
+        return Ok(line.to_vec());
+    }
+    let matches = pattern.find_spans(trim_line_ending(line))?;
+    Ok(splice(
+        line,
+        matches
+            .into_iter()
+            .map(|span| (span.clone(), line[span].to_vec())),
+        true,
+    ))
+}
 
-    let pattern = matches.get_one::<String>("pattern").unwrap();
-    let pattern = RegexBuilder::new(pattern)
-        .case_insensitive(matches.get_flag("insensitive"))
-        .build()
-        .map_err(|_| format!("Invalid pattern \"{}\"", pattern))?;
+// Expands the template for each match, as with Regex::replace_all
+fn replace_matches(
+    pattern: &dyn Matcher,
+    line: &[u8],
+    template: &[u8],
+    color: bool,
+) -> MyResult<Vec<u8>> {
+    let replacements = pattern.expand_matches(trim_line_ending(line), template)?;
+    Ok(splice(line, replacements, color))
+}
 
-    Ok(Config {
-        pattern,
-        files: matches
-            .get_many::<String>("files")
-            .unwrap()
-            .cloned()
-            .collect(),
-        recursive: matches.get_flag("recursive"),
-        count: matches.get_flag("count"),
-        invert_match: matches.get_flag("invert"),
-    })
+// Puts each part in place of its span, painted if color is on
+fn splice<I>(text: &[u8], parts: I, color: bool) -> Vec<u8>
+where
+    I: IntoIterator<Item = (Range<usize>, Vec<u8>)>,
+{
+    let mut result = vec![];
+    let mut last = 0;
+    for (span, part) in parts {
+        result.extend_from_slice(&text[last..span.start]);
+        result.extend(paint_bytes(&part, MATCH_COLOR, color));
+        last = span.end;
+    }
+    result.extend_from_slice(&text[last..]);
+    result
+}
+
+// Patterns see a line without its terminator so `$` and -x work on CRLF input
+fn trim_line_ending(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n")
+        .map(|l| l.strip_suffix(b"\r").unwrap_or(l))
+        .unwrap_or(line)
+}
+
+fn read_patterns(filename: &str) -> MyResult<Vec<String>> {
+    let file = open(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    file.lines()
+        .map(|line| line.map_err(|e| From::from(format!("{}: {}", filename, e))))
+        .collect()
 }
 
-pub fn run(config: Config) -> MyResult<()> {
-    let entries = find_files(&config.files, config.recursive);
-    let num_files = entries.len();
-    let print = |fname: &str, val: &str| {
-        if num_files > 1 {
-            print!("{}:{}", fname, val);
+// Runs the pattern over a whole buffer so matches can span lines. Each run of
+// lines touched by matches goes to the sink as one record, and the count is
+// of lines, so -c and -m mean the same as when matching line by line.
+fn find_multiline<F>(
+    buffer: &[u8],
+    pattern: &dyn Matcher,
+    options: &SearchOptions,
+    mut sink: F,
+) -> MyResult<usize>
+where
+    F: FnMut(MatchedLine) -> MyResult<bool>,
+{
+    let mut starts = vec![];
+    let mut offset = 0;
+    for line in buffer.split_inclusive(|&b| b == b'\n') {
+        starts.push(offset);
+        offset += line.len();
+    }
+    let num_lines = starts.len();
+    let line_of = |pos: usize| starts.partition_point(|&start| start <= pos) - 1;
+
+    // Inclusive ranges of the lines each match touches, merged where they overlap
+    let mut matched: Vec<(usize, usize)> = vec![];
+    for span in pattern.find_spans(buffer)? {
+        // The end of the buffer is only a line of its own if it lacks a newline
+        if span.start == buffer.len() && (buffer.is_empty() || buffer.ends_with(b"\n")) {
+            continue;
+        }
+        let first = line_of(span.start);
+        let last = if span.is_empty() {
+            first
         } else {
-            print!("{}", val);
+            line_of(span.end - 1)
+        };
+        match matched.last_mut() {
+            Some(range) if first <= range.1 => range.1 = range.1.max(last),
+            _ => matched.push((first, last)),
         }
+    }
+    let selected = if options.invert_match {
+        let mut unmatched = vec![];
+        let mut next = 0;
+        for &(first, last) in &matched {
+            unmatched.extend((next..first).map(|i| (i, i)));
+            next = last + 1;
+        }
+        unmatched.extend((next..num_lines).map(|i| (i, i)));
+        unmatched
+    } else {
+        matched
     };
 
-    for entry in entries {
-        match entry {
-            Err(e) => eprintln!("{}", e),
-            Ok(filename) => match open(&filename) {
-                Err(e) => eprintln!("{}: {}", filename, e),
-                Ok(file) => match find_lines(file, &config.pattern, config.invert_match) {
-                    Ok(matches) => {
-                        if config.count {
-                            print(&filename, &format!("{}\n", matches.len()));
-                        } else {
-                            for line in &matches {
-                                print(&filename, line);
-                            }
-                        }
-                    }
-                    Err(e) => eprintln!("{}", e),
-                },
-            },
+    let record = |kind, first: usize, last: usize| {
+        let end = starts.get(last + 1).copied().unwrap_or(buffer.len());
+        MatchedLine {
+            kind,
+            line_num: first + 1,
+            byte_offset: starts[first],
+            line: buffer[starts[first]..end].to_vec(),
         }
+    };
+    let mut count = 0;
+    let mut next_line = 0;
+    let mut after_end = 0;
+    for (first, last) in selected {
+        if options.max_count.is_some_and(|max| count >= max) {
+            break;
+        }
+        // Trailing context of the last run, then leading context of this one
+        for i in next_line..first {
+            let in_context = i < after_end || i + options.before_context >= first;
+            if in_context && !sink(record(LineKind::Context, i, i))? {
+                return Ok(count);
+            }
+        }
+        count += last - first + 1;
+        if !sink(record(LineKind::Match, first, last))? {
+            return Ok(count);
+        }
+        next_line = last + 1;
+        after_end = next_line + options.after_context;
     }
-    Ok(())
+    for i in next_line..after_end.min(num_lines) {
+        if !sink(record(LineKind::Context, i, i))? {
+            break;
+        }
+    }
+
+    Ok(count)
+}
+
+fn build_pattern(
+    patterns: &[String],
+    fixed_strings: bool,
+    insensitive: bool,
+    anchor: Anchor,
+    multiline: bool,
+) -> MyResult<Regex> {
+    // An empty pattern list selects nothing
+    if patterns.is_empty() {
+        return Ok(Regex::new(r"[^\s\S]")?);
+    }
+
+    let alternatives: Vec<String> = if fixed_strings {
+        // Longer literals go first so the leftmost match is also the longest
+        let mut literals: Vec<&String> = patterns.iter().collect();
+        literals.sort_by_key(|p| std::cmp::Reverse(p.len()));
+        literals.into_iter().map(|p| regex::escape(p)).collect()
+    } else {
+        // Check each pattern alone so a stray parenthesis cannot leak
+        // into its neighbours once they are joined
+        for pattern in patterns {
+            Regex::new(pattern).map_err(|e| {
+                // Point out the engine that can handle what this one cannot
+                let needs_pcre = ["look-around", "backreferences"]
+                    .iter()
+                    .any(|feature| e.to_string().contains(feature));
+                let hint = if needs_pcre {
+                    " (lookaround and backreferences need -P)"
+                } else {
+                    ""
+                };
+                format!("Invalid pattern \"{}\"{}", pattern, hint)
+            })?;
+        }
+        patterns.iter().map(|p| format!("(?:{})", p)).collect()
+    };
+
+    // A single alternation lets the regex engine match the whole set in one
+    // pass, using Aho-Corasick for large literal sets
+    let alternation = alternatives.join("|");
+    let pattern = match anchor {
+        Anchor::None => alternation,
+        Anchor::Word => format!(r"\b{{start-half}}(?:{})\b{{end-half}}", alternation),
+        Anchor::Line => format!("^(?:{})$", alternation),
+    };
+    // Across a whole buffer ^ and $ still mean the ends of a line
+    RegexBuilder::new(&pattern)
+        .case_insensitive(insensitive)
+        .multi_line(multiline)
+        .crlf(multiline)
+        .size_limit(1 << 30)
+        .dfa_size_limit(1 << 30)
+        .build()
+        .map_err(|e| From::from(format!("Invalid pattern: {}", e)))
+}
+
+#[cfg(not(feature = "pcre"))]
+fn build_pcre(
+    _patterns: &[String],
+    _fixed_strings: bool,
+    _insensitive: bool,
+    _anchor: Anchor,
+    _multiline: bool,
+) -> MyResult<Box<dyn Matcher>> {
+    Err(From::from(
+        "-P/--pcre is not supported by this build; rebuild with --features pcre",
+    ))
+}
+
+fn parse_num(val: &str) -> MyResult<usize> {
+    val.parse().map_err(|_| From::from(val))
 }
 
 fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
@@ -128,7 +1628,53 @@ fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
     }
 }
 
-fn find_files(paths: &[String], recursive: bool) -> Vec<MyResult<String>> {
+// Decodes the input as it is read if it starts with a known magic number
+fn decompress(mut file: Box<dyn BufRead>) -> MyResult<Box<dyn BufRead>> {
+    let magic = file.fill_buf()?;
+    let decoded: Box<dyn Read> = if magic.starts_with(b"\x1f\x8b") {
+        Box::new(MultiGzDecoder::new(file))
+    } else if magic.starts_with(b"BZh") {
+        Box::new(MultiBzDecoder::new(file))
+    } else if magic.starts_with(b"\xfd7zXZ\x00") {
+        Box::new(XzDecoder::new_multi_decoder(file))
+    } else if magic.starts_with(b"\x28\xb5\x2f\xfd") {
+        Box::new(zstd::Decoder::with_buffer(file)?)
+    } else {
+        return Ok(file);
+    };
+    Ok(Box::new(BufReader::new(decoded)))
+}
+
+fn build_globs<'a>(globs: impl Iterator<Item = &'a str>) -> MyResult<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).map_err(|e| format!("Invalid glob \"{}\": {}", glob, e))?);
+    }
+    Ok(builder.build()?)
+}
+
+impl FileFilter {
+    // Globs are checked against the file name, and a command-line path may
+    // also match as a whole
+    fn keeps_file(&self, path: &Path, from_command_line: bool) -> bool {
+        let matches = |globs: &GlobSet| {
+            path.file_name().is_some_and(|name| globs.is_match(name))
+                || (from_command_line && globs.is_match(path))
+        };
+        !matches(&self.exclude) && self.include.as_ref().is_none_or(matches)
+    }
+
+    fn keeps_dir(&self, path: &Path) -> bool {
+        path.file_name()
+            .is_none_or(|name| !self.exclude_dir.is_match(name))
+    }
+}
+
+fn find_files(
+    paths: &[String],
+    recursive: bool,
+    filter: &FileFilter,
+) -> Vec<Result<String, FindError>> {
     let mut results = vec![];
 
     for path in paths {
@@ -137,22 +1683,46 @@ fn find_files(paths: &[String], recursive: bool) -> Vec<MyResult<String>> {
             _ => match fs::metadata(path) {
                 Ok(metadata) => {
                     if metadata.is_dir() {
-                        if recursive {
+                        if !recursive {
+                            results.push(Err(FindError::IsDirectory(path.to_string())));
+                        } else if !filter.keeps_dir(Path::new(path)) {
+                            continue;
+                        } else if filter.ignore_files {
+                            let exclude_dir = filter.exclude_dir.clone();
+                            for entry in WalkBuilder::new(path)
+                                .require_git(false)
+                                .filter_entry(move |e| {
+                                    e.depth() == 0
+                                        || !e.file_type().is_some_and(|t| t.is_dir())
+                                        || !exclude_dir.is_match(e.file_name())
+                                })
+                                .build()
+                                .flatten()
+                                .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
+                                .filter(|e| filter.keeps_file(e.path(), false))
+                            {
+                                results.push(Ok(entry.path().display().to_string()));
+                            }
+                        } else {
                             for entry in WalkDir::new(path)
                                 .into_iter()
+                                .filter_entry(|e| {
+                                    e.depth() == 0
+                                        || !e.file_type().is_dir()
+                                        || filter.keeps_dir(e.path())
+                                })
                                 .flatten()
                                 .filter(|e| e.file_type().is_file())
+                                .filter(|e| filter.keeps_file(e.path(), false))
                             {
                                 results.push(Ok(entry.path().display().to_string()));
                             }
-                        } else {
-                            results.push(Err(From::from(format!("{} is a directory", path))));
                         }
-                    } else if metadata.is_file() {
+                    } else if metadata.is_file() && filter.keeps_file(Path::new(path), true) {
                         results.push(Ok(path.to_string()));
                     }
                 }
-                Err(e) => results.push(Err(From::from(format!("{}: {}", path, e)))),
+                Err(e) => results.push(Err(FindError::Io(path.to_string(), e))),
             },
         }
     }
@@ -160,34 +1730,98 @@ fn find_files(paths: &[String], recursive: bool) -> Vec<MyResult<String>> {
     results
 }
 
-fn find_lines<T: BufRead>(
+// Hands each selected and context line to `sink` as soon as it is read and
+// returns the number of selected lines. The sink returns false to stop early.
+fn find_lines<T, F>(
     mut file: T,
-    pattern: &Regex,
-    invert_match: bool,
-) -> MyResult<Vec<String>> {
-    let mut matches = vec![];
-    let mut line = String::new();
+    pattern: &dyn Matcher,
+    options: &SearchOptions,
+    mut sink: F,
+) -> MyResult<usize>
+where
+    T: BufRead,
+    F: FnMut(MatchedLine) -> MyResult<bool>,
+{
+    let mut count = 0;
+    let mut before = VecDeque::with_capacity(options.before_context);
+    let mut after_remaining = 0;
+    let mut line = vec![];
+    let mut line_num = 0;
+    let mut byte_offset = 0;
 
     loop {
-        let bytes = file.read_line(&mut line)?;
+        // Past the last allowed match only the trailing context is still read
+        let done = options.max_count.is_some_and(|max| count >= max);
+        if done && after_remaining == 0 {
+            break;
+        }
+        let bytes = file.read_until(b'\n', &mut line)?;
         if bytes == 0 {
             break;
         }
-        if pattern.is_match(&line) ^ invert_match {
-            matches.push(mem::take(&mut line));
+        line_num += 1;
+        let record = |kind, line| MatchedLine {
+            kind,
+            line_num,
+            byte_offset,
+            line,
+        };
+        if !done && pattern.is_match(trim_line_ending(&line))? ^ options.invert_match {
+            for context in before.drain(..) {
+                if !sink(context)? {
+                    return Ok(count);
+                }
+            }
+            count += 1;
+            if !sink(record(LineKind::Match, mem::take(&mut line)))? {
+                break;
+            }
+            after_remaining = options.after_context;
+        } else if after_remaining > 0 {
+            after_remaining -= 1;
+            if !sink(record(LineKind::Context, mem::take(&mut line)))? {
+                break;
+            }
+        } else if options.before_context > 0 {
+            if before.len() == options.before_context {
+                before.pop_front();
+            }
+            before.push_back(record(LineKind::Context, mem::take(&mut line)));
         }
+        byte_offset += bytes;
         line.clear();
     }
 
-    Ok(matches)
+    Ok(count)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{find_files, find_lines};
+    use super::{
+        build_globs, build_pattern, find_files, find_lines, find_multiline, highlight, is_binary,
+        json_line, replace_matches, trim_line_ending, Anchor, FileFilter, LineKind, MatchedLine,
+        SearchOptions,
+    };
     use rand::{distributions::Alphanumeric, Rng};
-    use regex::{Regex, RegexBuilder};
-    use std::io::Cursor;
+    use regex::bytes::{Regex, RegexBuilder};
+    use std::io::{BufRead, Cursor};
+
+    const INVERT: SearchOptions = SearchOptions {
+        invert_match: true,
+        before_context: 0,
+        after_context: 0,
+        max_count: None,
+    };
+
+    fn collect<T: BufRead>(file: T, pattern: &Regex, options: &SearchOptions) -> Vec<MatchedLine> {
+        let mut lines = vec![];
+        find_lines(file, pattern, options, |matched| {
+            lines.push(matched);
+            Ok(true)
+        })
+        .unwrap();
+        lines
+    }
 
     #[test]
     fn test_find_lines() {
@@ -195,14 +1829,16 @@ mod tests {
 
         // The pattern _or_ should match the one line, "Lorem"
         let re1 = Regex::new("or").unwrap();
-        let matches = find_lines(Cursor::new(&text), &re1, false);
+        let matches = find_lines(Cursor::new(&text), &re1, &SearchOptions::default(), |_| {
+            Ok(true)
+        });
         assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 1);
+        assert_eq!(matches.unwrap(), 1);
 
         // When inverted, the function should match the other two lines
-        let matches = find_lines(Cursor::new(&text), &re1, true);
+        let matches = find_lines(Cursor::new(&text), &re1, &INVERT, |_| Ok(true));
         assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 2);
+        assert_eq!(matches.unwrap(), 2);
 
         // This regex will be case-insensitive
         let re2 = RegexBuilder::new("or")
@@ -211,32 +1847,445 @@ mod tests {
             .unwrap();
 
         // The two lines "Lorem" and "DOLOR" should match
-        let matches = find_lines(Cursor::new(&text), &re2, false);
+        let matches = find_lines(Cursor::new(&text), &re2, &SearchOptions::default(), |_| {
+            Ok(true)
+        });
         assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 2);
+        assert_eq!(matches.unwrap(), 2);
 
         // When inverted, the one remaining line should match
-        let matches = find_lines(Cursor::new(&text), &re2, true);
+        let matches = find_lines(Cursor::new(&text), &re2, &INVERT, |_| Ok(true));
         assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 1);
+        assert_eq!(matches.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_find_lines_positions() {
+        let text = b"Lorem\nIpsum\r\nDOLOR";
+        let re = RegexBuilder::new("or")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+
+        // Line numbers are 1-based and offsets count the line endings
+        let matches = collect(Cursor::new(&text), &re, &SearchOptions::default());
+        assert_eq!(
+            matches,
+            vec![
+                MatchedLine {
+                    kind: LineKind::Match,
+                    line_num: 1,
+                    byte_offset: 0,
+                    line: b"Lorem\n".to_vec(),
+                },
+                MatchedLine {
+                    kind: LineKind::Match,
+                    line_num: 3,
+                    byte_offset: 13,
+                    line: b"DOLOR".to_vec(),
+                },
+            ]
+        );
+
+        // Inverted matches keep their original positions
+        let matches = collect(Cursor::new(&text), &re, &INVERT);
+        assert_eq!(
+            matches,
+            vec![MatchedLine {
+                kind: LineKind::Match,
+                line_num: 2,
+                byte_offset: 6,
+                line: b"Ipsum\r\n".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_lines_line_regexp() {
+        let text = b"Lorem\nIpsum\r\nDOLOR";
+        let patterns = vec!["ipsum".to_string(), "dolor".to_string()];
+        let re = build_pattern(&patterns, true, true, Anchor::Line, false).unwrap();
+
+        // Whole lines match with or without a CRLF ending
+        let matches = collect(Cursor::new(&text), &re, &SearchOptions::default());
+        assert_eq!(
+            matches.iter().map(|m| m.line_num).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        // When inverted, only the first line is left
+        let matches = collect(Cursor::new(&text), &re, &INVERT);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].line, b"Lorem\n");
+    }
+
+    #[test]
+    fn test_find_lines_max_count() {
+        let re = Regex::new("or").unwrap();
+        let first = SearchOptions {
+            max_count: Some(1),
+            ..Default::default()
+        };
+
+        // Reading stops at the first match
+        let mut seen = 0;
+        let res = find_lines(Cursor::new(b"Lorem\nor\n"), &re, &first, |_| {
+            seen += 1;
+            Ok(true)
+        });
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(seen, 1);
+
+        // Inverted matches are limited the same way
+        let options = SearchOptions {
+            invert_match: true,
+            ..first
+        };
+        let matches = collect(Cursor::new(b"Lorem\nIpsum\r\nDOLOR"), &re, &options);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].line_num, 2);
+
+        // Trailing context is still read after the last allowed match
+        let options = SearchOptions {
+            after_context: 2,
+            ..first
+        };
+        let matches = collect(Cursor::new(b"Lorem\nIpsum\nor\nDOLOR"), &re, &options);
+        assert_eq!(
+            matches
+                .iter()
+                .map(|m| (m.line_num, m.kind == LineKind::Match))
+                .collect::<Vec<_>>(),
+            vec![(1, true), (2, false), (3, false)]
+        );
+
+        // Nothing is read with a limit of zero
+        let none = SearchOptions {
+            max_count: Some(0),
+            ..Default::default()
+        };
+        let res = find_lines(Cursor::new(b"or\n"), &re, &none, |_| Ok(true));
+        assert_eq!(res.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_find_lines_context() {
+        let text = b"one\ntwo\nthree\nfour\nfive\nsix\n";
+        let re = Regex::new("three").unwrap();
+        let kinds = |matches: Vec<MatchedLine>| {
+            matches
+                .iter()
+                .map(|m| (m.line_num, m.kind == LineKind::Match))
+                .collect::<Vec<_>>()
+        };
+
+        // Leading and trailing context surround the match
+        let options = SearchOptions {
+            before_context: 1,
+            after_context: 2,
+            ..Default::default()
+        };
+        let matches = collect(Cursor::new(&text), &re, &options);
+        assert_eq!(
+            kinds(matches),
+            vec![(2, false), (3, true), (4, false), (5, false)]
+        );
+
+        // Leading context is limited to the start of the file
+        let options = SearchOptions {
+            before_context: 5,
+            ..Default::default()
+        };
+        let matches = collect(Cursor::new(&text), &re, &options);
+        assert_eq!(kinds(matches), vec![(1, false), (2, false), (3, true)]);
+
+        // When inverted, the matching line becomes the context
+        let re = Regex::new("o").unwrap();
+        let options = SearchOptions {
+            invert_match: true,
+            before_context: 1,
+            after_context: 1,
+            ..Default::default()
+        };
+        let matches = collect(Cursor::new(&text), &re, &options);
+        assert_eq!(
+            kinds(matches),
+            vec![(2, false), (3, true), (4, false), (5, true), (6, true)]
+        );
+    }
+
+    #[test]
+    fn test_find_multiline() {
+        let text = b"fn a(\n    self,\n) {}\nfn b(x) {}\nfn c(\n    self) {}";
+        let re = RegexBuilder::new(r"fn \w+\(\n\s+self")
+            .multi_line(true)
+            .build()
+            .unwrap();
+        let find = |options: &SearchOptions| {
+            let mut lines = vec![];
+            let count = find_multiline(text, &re, options, |matched| {
+                lines.push(matched);
+                Ok(true)
+            })
+            .unwrap();
+            (count, lines)
+        };
+
+        // Each match is one record holding every line it touches
+        let (count, matches) = find(&Default::default());
+        assert_eq!(count, 4);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].line_num, 1);
+        assert_eq!(matches[0].byte_offset, 0);
+        assert_eq!(matches[0].line, b"fn a(\n    self,\n");
+        assert_eq!(matches[1].line_num, 5);
+        assert_eq!(matches[1].byte_offset, 32);
+        assert_eq!(matches[1].line, b"fn c(\n    self) {}");
+
+        // The max count is of lines but never splits a match
+        let (count, matches) = find(&SearchOptions {
+            max_count: Some(1),
+            after_context: 1,
+            ..Default::default()
+        });
+        assert_eq!(count, 2);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[1].kind, LineKind::Context);
+        assert_eq!(matches[1].line, b") {}\n");
+
+        // Inverting selects the lines no match touches, one at a time
+        let (count, matches) = find(&INVERT);
+        assert_eq!(count, 2);
+        let lines: Vec<_> = matches.iter().map(|m| m.line_num).collect();
+        assert_eq!(lines, [3, 4]);
+
+        // A trailing newline does not start another line
+        let re = Regex::new("(?m)^").unwrap();
+        let count = find_multiline(b"a\nb\n", &re, &Default::default(), |_| Ok(true)).unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_build_pattern() {
+        let patterns = vec!["fox".to_string(), "d.g".to_string()];
+
+        // Any of the patterns can match
+        let re = build_pattern(&patterns, false, false, Anchor::None, false).unwrap();
+        assert!(re.is_match(b"The quick brown fox"));
+        assert!(re.is_match(b"lazy dog"));
+        assert!(!re.is_match(b"cat"));
+
+        // Fixed strings treat metacharacters literally
+        let re = build_pattern(&patterns, true, false, Anchor::None, false).unwrap();
+        assert!(!re.is_match(b"lazy dog"));
+        assert!(re.is_match(b"d.g"));
+
+        // The longest literal wins at the same position
+        let patterns = vec!["ab".to_string(), "abc".to_string()];
+        let re = build_pattern(&patterns, true, false, Anchor::None, false).unwrap();
+        assert_eq!(re.find(b"xabcx").unwrap().as_bytes(), b"abc");
+
+        // Case-insensitive applies to every pattern
+        let re = build_pattern(&patterns, true, true, Anchor::None, false).unwrap();
+        assert!(re.is_match(b"ABC"));
+
+        // An empty list never matches
+        let re = build_pattern(&[], false, false, Anchor::None, false).unwrap();
+        assert!(!re.is_match(b""));
+        assert!(!re.is_match(b"\n"));
+
+        // A bad pattern is reported on its own
+        let patterns = vec!["fox".to_string(), "a)|(b".to_string()];
+        let res = build_pattern(&patterns, false, false, Anchor::None, false);
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "Invalid pattern \"a)|(b\"".to_string()
+        );
+    }
+
+    #[test]
+    fn test_build_pattern_anchor() {
+        let patterns = vec!["the".to_string()];
+
+        // Whole words are bounded by non-word characters or the line edges
+        let re = build_pattern(&patterns, false, false, Anchor::Word, false).unwrap();
+        assert!(re.is_match(b"the heart"));
+        assert!(re.is_match(b"up the"));
+        assert!(!re.is_match(b"Then there's"));
+        let fixed = vec!["-foo".to_string()];
+        let re = build_pattern(&fixed, true, false, Anchor::Word, false).unwrap();
+        assert!(re.is_match(b"a -foo b"));
+        assert!(!re.is_match(b"a -foobar"));
+
+        // Whole lines must match from start to end
+        let re = build_pattern(&patterns, false, true, Anchor::Line, false).unwrap();
+        assert!(re.is_match(b"THE"));
+        assert!(!re.is_match(b"the end"));
+        let re = build_pattern(&fixed, true, false, Anchor::Line, false).unwrap();
+        assert!(re.is_match(b"-foo"));
+        assert!(!re.is_match(b"-foo-"));
+    }
+
+    #[cfg(feature = "pcre")]
+    #[test]
+    fn test_build_pcre() {
+        use super::{build_pcre, Range};
+
+        let patterns = vec![r"(\w+) \1".to_string(), "(?<!s)he".to_string()];
+        let re = build_pcre(&patterns, false, true, Anchor::None, false).unwrap();
+        assert!(re.is_match(b"THE the end").unwrap());
+        assert!(re.is_match(b"a hero").unwrap());
+        assert!(!re.is_match(b"she said").unwrap());
+        assert_eq!(
+            re.find_spans(b"so so").unwrap(),
+            vec![Range { start: 0, end: 5 }]
+        );
+        assert_eq!(
+            re.expand_matches(b"so so", b"[$1]").unwrap(),
+            [(0..5, b"[so]".to_vec())]
+        );
+
+        // Bytes that are not UTF-8 are skipped over, and spans are still in bytes
+        assert!(re.is_match(b"caf\xe9 the the").unwrap());
+        assert_eq!(re.find_spans(b"\xff\xfe the the").unwrap(), vec![3..10]);
+        assert_eq!(
+            re.expand_matches(b"\xff so so \xff", b"$1").unwrap(),
+            [(2..7, b"so".to_vec())]
+        );
+        let any = vec!["(.) (.)".to_string()];
+        let re = build_pcre(&any, false, false, Anchor::None, false).unwrap();
+        assert_eq!(
+            re.expand_matches(b"\xe9 \xff", b"$2${1}$$").unwrap(),
+            [(0..3, b"\xff\xe9$".to_vec())]
+        );
+
+        // A search that hits the backtracking limit is an error, not a miss
+        let slow = vec!["(a|aa)*(?=b)c".to_string()];
+        let re = build_pcre(&slow, false, false, Anchor::None, false).unwrap();
+        assert!(re.is_match(&[b'a'; 40]).is_err());
+
+        // Whole words mean no word character on either side
+        let fixed = vec!["a.b".to_string()];
+        let re = build_pcre(&fixed, true, false, Anchor::Word, false).unwrap();
+        assert!(re.is_match(b"x a.b y").unwrap());
+        assert!(!re.is_match(b"xa.b").unwrap());
+        assert!(!re.is_match(b"a_b").unwrap());
+
+        let bad = vec!["(?<=".to_string()];
+        assert!(build_pcre(&bad, false, false, Anchor::None, false).is_err());
+    }
+
+    #[test]
+    fn test_trim_line_ending() {
+        assert_eq!(trim_line_ending(b"Lorem\n"), b"Lorem");
+        assert_eq!(trim_line_ending(b"Ipsum\r\n"), b"Ipsum");
+        assert_eq!(trim_line_ending(b"DOLOR"), b"DOLOR");
+        assert_eq!(trim_line_ending(b"a\rb"), b"a\rb");
+    }
+
+    #[test]
+    fn test_is_binary() {
+        assert!(!is_binary(&mut Cursor::new(b"Lorem\nIpsum\r\n")).unwrap());
+        assert!(!is_binary(&mut Cursor::new(b"caf\xe9\n")).unwrap());
+        assert!(is_binary(&mut Cursor::new(b"foo\0bar\n")).unwrap());
+        assert!(!is_binary(&mut Cursor::new(b"")).unwrap());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let re = RegexBuilder::new("the")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let line = b"The sweeping up the heart,\n";
+
+        // Nothing changes when color is disabled
+        assert_eq!(highlight(&re, line, false).unwrap(), line);
+
+        // Every match on the line is wrapped in escapes
+        assert_eq!(
+            highlight(&re, line, true).unwrap(),
+            b"\x1b[01;31m\x1b[KThe\x1b[m\x1b[K sweeping up \
+              \x1b[01;31m\x1b[Kthe\x1b[m\x1b[K heart,\n"
+        );
+    }
+
+    #[test]
+    fn test_replace_matches() {
+        let re = Regex::new(r"(?<first>\w)(\w*)").unwrap();
+        assert_eq!(
+            replace_matches(&re, b"quick brown\r\n", b"$2${first}ay", false).unwrap(),
+            b"uickqay rownbay\r\n"
+        );
+
+        // Only the replacement is highlighted
+        assert_eq!(
+            replace_matches(&re, b"a-b", b"[$1]", true).unwrap(),
+            b"\x1b[01;31m\x1b[K[a]\x1b[m\x1b[K-\x1b[01;31m\x1b[K[b]\x1b[m\x1b[K"
+        );
+    }
+
+    #[test]
+    fn test_json_line() {
+        let re = Regex::new("o").unwrap();
+        let matched = MatchedLine {
+            kind: LineKind::Match,
+            line_num: 3,
+            byte_offset: 10,
+            line: b"foo\r\n".to_vec(),
+        };
+        assert_eq!(
+            json_line(&re, "a:b.txt", &matched).unwrap().to_string(),
+            r#"{"type":"match","data":{"path":"a:b.txt","line_number":3,"byte_offset":10,"text":"foo","submatches":[{"text":"o","start":1,"end":2},{"text":"o","start":2,"end":3}]}}"#
+        );
+
+        // Context lines carry no submatches even if they match
+        let context = MatchedLine {
+            kind: LineKind::Context,
+            ..matched
+        };
+        let value = json_line(&re, "a:b.txt", &context).unwrap();
+        assert_eq!(value["type"], "context");
+        assert_eq!(value["data"]["submatches"], serde_json::json!([]));
+
+        // Spans of a line that is not UTF-8 index the text as printed
+        let re = Regex::new("the").unwrap();
+        let latin1 = MatchedLine {
+            kind: LineKind::Match,
+            line_num: 1,
+            byte_offset: 0,
+            line: b"\xe9\xe9 the\n".to_vec(),
+        };
+        let value = json_line(&re, "a:b.txt", &latin1).unwrap();
+        let text = value["data"]["text"].as_str().unwrap();
+        let submatch = &value["data"]["submatches"][0];
+        assert_eq!(text, "\u{fffd}\u{fffd} the");
+        assert_eq!(
+            (&submatch["start"], &submatch["end"]),
+            (&7.into(), &10.into())
+        );
+        assert_eq!(&text[7..10], submatch["text"]);
     }
 
     #[test]
     fn test_find_files() {
+        let no_filter = FileFilter::default();
+
         // Verify that the function finds a file known to exist
-        let files = find_files(&["./tests/inputs/fox.txt".to_string()], false);
+        let files = find_files(&["./tests/inputs/fox.txt".to_string()], false, &no_filter);
         assert_eq!(files.len(), 1);
         assert_eq!(files[0].as_ref().unwrap(), "./tests/inputs/fox.txt");
 
         // The function should reject a directory without the recursive option
-        let files = find_files(&["./tests/inputs".to_string()], false);
+        let files = find_files(&["./tests/inputs".to_string()], false, &no_filter);
         assert_eq!(files.len(), 1);
         if let Err(e) = &files[0] {
             assert_eq!(e.to_string(), "./tests/inputs is a directory");
         }
 
         // Verify the function recurses to find four files in the directory
-        let res = find_files(&["./tests/inputs".to_string()], true);
+        let res = find_files(&["./tests/inputs".to_string()], true, &no_filter);
         let mut files: Vec<String> = res
             .iter()
             .map(|r| r.as_ref().unwrap().replace("\\", "/"))
@@ -261,8 +2310,63 @@ mod tests {
             .collect();
 
         // Verify that the function returns the bad file as an error
-        let files = find_files(&[bad], false);
+        let files = find_files(&[bad], false, &no_filter);
         assert_eq!(files.len(), 1);
         assert!(files[0].is_err());
     }
+
+    #[test]
+    fn test_find_files_filter() {
+        let find = |filter: &FileFilter| {
+            let mut files: Vec<String> = find_files(&["./tests/ignore".to_string()], true, filter)
+                .iter()
+                .map(|r| r.as_ref().unwrap().replace('\\', "/"))
+                .collect();
+            files.sort();
+            files
+        };
+
+        // Only files matching an include glob are kept
+        let filter = FileFilter {
+            include: Some(build_globs(["*.rs", "*.md"].into_iter()).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            find(&filter),
+            vec!["./tests/ignore/src/main.rs", "./tests/ignore/src/notes.md"]
+        );
+
+        // Excluded files and directories are skipped
+        let filter = FileFilter {
+            exclude: build_globs(["*.txt", ".*"].into_iter()).unwrap(),
+            exclude_dir: build_globs(["sr?"].into_iter()).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(find(&filter), Vec::<String>::new());
+
+        // Ignore files and hidden files are honored when asked
+        let filter = FileFilter {
+            ignore_files: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            find(&filter),
+            vec![
+                "./tests/ignore/kept.txt",
+                "./tests/ignore/src/main.rs",
+                "./tests/ignore/src/notes.md",
+            ]
+        );
+
+        // Command-line files are filtered too
+        let filter = FileFilter {
+            exclude: build_globs(["*/fox.txt"].into_iter()).unwrap(),
+            ..Default::default()
+        };
+        let files = find_files(&["tests/inputs/fox.txt".to_string()], false, &filter);
+        assert!(files.is_empty());
+
+        // A bad glob is an error
+        assert!(build_globs(["a[b"].into_iter()).is_err());
+    }
 }
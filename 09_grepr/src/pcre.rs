@@ -0,0 +1,163 @@
+use crate::{Anchor, Matcher, MyResult};
+use fancy_regex::{Regex, RegexBuilder};
+use std::{borrow::Cow, ops::Range, str};
+
+// Builds the backtracking matcher for -P the same way build_pattern builds
+// the default one
+pub fn build_pcre(
+    patterns: &[String],
+    fixed_strings: bool,
+    insensitive: bool,
+    anchor: Anchor,
+    multiline: bool,
+) -> MyResult<Box<dyn Matcher>> {
+    // An empty pattern list selects nothing
+    if patterns.is_empty() {
+        return Ok(Box::new(Regex::new("(?!)")?));
+    }
+
+    let alternatives: Vec<String> = if fixed_strings {
+        let mut literals: Vec<&String> = patterns.iter().collect();
+        literals.sort_by_key(|p| std::cmp::Reverse(p.len()));
+        literals
+            .into_iter()
+            .map(|p| fancy_regex::escape(p).into_owned())
+            .collect()
+    } else {
+        for pattern in patterns {
+            Regex::new(pattern)
+                .map_err(|e| format!("Invalid PCRE pattern \"{}\": {}", pattern, e))?;
+        }
+        patterns.iter().map(|p| format!("(?:{})", p)).collect()
+    };
+
+    // Lookaround gives grep's -w exactly: no word character on either side
+    let alternation = alternatives.join("|");
+    let pattern = match anchor {
+        Anchor::None => alternation,
+        Anchor::Word => format!(r"(?<!\w)(?:{})(?!\w)", alternation),
+        Anchor::Line => format!("^(?:{})$", alternation),
+    };
+    // Across a whole buffer ^ and $ still mean the ends of a line
+    let flags = if multiline { "(?m)" } else { "" };
+    let regex = RegexBuilder::new(&format!("{}{}", flags, pattern))
+        .case_insensitive(insensitive)
+        .build()
+        .map_err(|e| format!("Invalid PCRE pattern: {}", e))?;
+    Ok(Box::new(regex))
+}
+
+// fancy-regex searches strings, so text that is not UTF-8 is searched with
+// each invalid byte read as U+FFFD and the spans mapped back to bytes
+impl Matcher for Regex {
+    fn is_match(&self, text: &[u8]) -> MyResult<bool> {
+        Ok(Regex::is_match(self, &Decoded::new(text).text)?)
+    }
+
+    fn find_spans(&self, text: &[u8]) -> MyResult<Vec<Range<usize>>> {
+        let decoded = Decoded::new(text);
+        self.find_iter(&decoded.text)
+            .map(|m| Ok(decoded.span(m?.range())))
+            .collect()
+    }
+
+    fn expand_matches(
+        &self,
+        text: &[u8],
+        template: &[u8],
+    ) -> MyResult<Vec<(Range<usize>, Vec<u8>)>> {
+        let decoded = Decoded::new(text);
+        self.captures_iter(&decoded.text)
+            .map(|caps| {
+                let caps = caps?;
+                // Groups are copied from the original bytes, not the decoded text
+                let group = |m: Option<fancy_regex::Match>| match m {
+                    Some(m) => &text[decoded.span(m.range())],
+                    None => &[],
+                };
+                let replaced = expand(template, |name| match name.parse() {
+                    Ok(i) => group(caps.get(i)),
+                    Err(_) => group(caps.name(name)),
+                });
+                Ok((decoded.span(caps.get(0).unwrap().range()), replaced))
+            })
+            .collect()
+    }
+}
+
+// The text as a string, along with where each U+FFFD that stands in for an
+// invalid byte starts
+struct Decoded<'a> {
+    text: Cow<'a, str>,
+    invalid: Vec<usize>,
+}
+
+impl<'a> Decoded<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        if let Ok(text) = str::from_utf8(bytes) {
+            return Decoded {
+                text: Cow::Borrowed(text),
+                invalid: vec![],
+            };
+        }
+        let mut text = String::with_capacity(bytes.len());
+        let mut invalid = vec![];
+        for chunk in bytes.utf8_chunks() {
+            text.push_str(chunk.valid());
+            for _ in chunk.invalid() {
+                invalid.push(text.len());
+                text.push(char::REPLACEMENT_CHARACTER);
+            }
+        }
+        Decoded {
+            text: Cow::Owned(text),
+            invalid,
+        }
+    }
+
+    // Each U+FFFD before `pos` takes three bytes for the one it replaced
+    fn offset(&self, pos: usize) -> usize {
+        pos - 2 * self.invalid.partition_point(|&at| at < pos)
+    }
+
+    fn span(&self, span: Range<usize>) -> Range<usize> {
+        self.offset(span.start)..self.offset(span.end)
+    }
+}
+
+// Fills in $N, ${N}, $name and ${name} the way regex::bytes::Captures::expand
+// does, with $$ for a literal dollar sign and unknown groups left empty
+fn expand<'t>(template: &[u8], group: impl Fn(&str) -> &'t [u8]) -> Vec<u8> {
+    let mut expanded = vec![];
+    let mut rest = template;
+    while let Some(dollar) = rest.iter().position(|&b| b == b'$') {
+        expanded.extend_from_slice(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+        if rest.first() == Some(&b'$') {
+            expanded.push(b'$');
+            rest = &rest[1..];
+            continue;
+        }
+        let (name, len) = if rest.first() == Some(&b'{') {
+            match rest.iter().position(|&b| b == b'}') {
+                Some(end) => (&rest[1..end], end + 1),
+                None => (&rest[..0], 0),
+            }
+        } else {
+            let end = rest
+                .iter()
+                .position(|&b| !(b.is_ascii_alphanumeric() || b == b'_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], end)
+        };
+        match str::from_utf8(name) {
+            Ok(name) if !name.is_empty() => {
+                expanded.extend_from_slice(group(name));
+                rest = &rest[len..];
+            }
+            _ => expanded.push(b'$'),
+        }
+    }
+    expanded.extend_from_slice(rest);
+    expanded
+}
@@ -1,3 +1,6 @@
+// Some tests pass borrowed arrays and paths where clippy wants values
+#![allow(clippy::needless_borrow, clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
@@ -12,6 +15,12 @@ const EMPTY: &str = "tests/inputs/empty.txt";
 const FOX: &str = "tests/inputs/fox.txt";
 const NOBODY: &str = "tests/inputs/nobody.txt";
 const INPUTS_DIR: &str = "tests/inputs";
+const IGNORE_DIR: &str = "tests/ignore";
+const WORDS: &str = "tests/patterns/words.txt";
+const LITERALS: &str = "tests/patterns/literals.txt";
+const NUL_DAT: &str = "tests/binary/nul.dat";
+const LATIN1: &str = "tests/binary/latin1.txt";
+const MULTILINE: &str = "tests/multiline/methods.txt";
 
 // --------------------------------------------------
 fn gen_bad_file() -> String {
@@ -273,3 +282,1096 @@ fn stdin_insensitive_count() -> TestResult {
         .stdout(expected);
     Ok(())
 }
+
+// --------------------------------------------------
+#[test]
+fn bustle_line_number() -> TestResult {
+    run(
+        &["--line-number", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.line_number",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_byte_offset() -> TestResult {
+    run(
+        &["-b", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.byte_offset",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_line_number_byte_offset() -> TestResult {
+    run(
+        &["-nbi", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.line_number.byte_offset",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_context() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-C", "x", "the", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "x: invalid context length argument",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_after_context() -> TestResult {
+    run(
+        &["-A", "1", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.after_context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_before_context() -> TestResult {
+    run(
+        &["--before-context", "2", "-n", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.before_context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_invert_context() -> TestResult {
+    run(
+        &["-C1", "-vn", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.invert.context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_context() -> TestResult {
+    run(
+        &["--context", "1", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_only_matching() -> TestResult {
+    run(
+        &["-o", "-nb", "-i", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.insensitive.only_matching",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_only_matching_context() -> TestResult {
+    run(
+        &["--only-matching", "-C1", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.only_matching.context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_color() -> TestResult {
+    run(
+        &["--color=always", "-ni", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.color",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_invert_context_color() -> TestResult {
+    run(
+        &["--color=always", "-v", "-C1", "-n", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.invert.context.color",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_auto_not_terminal() -> TestResult {
+    run(
+        &["--color=auto", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_pattern_file() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!("{}: .* [(]os error 2[)]", bad);
+    Command::cargo_bin(PRG)?
+        .args(["-f", &bad, FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::is_match(expected)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_regexp() -> TestResult {
+    run(
+        &["-e", "fox", "--regexp", "Nobody", FOX, NOBODY],
+        "tests/expected/fox.nobody.regexp",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn regexp_and_pattern_file_count() -> TestResult {
+    run(
+        &["-ci", "-e", "the", "-f", WORDS, BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.words.insensitive.count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pattern_file() -> TestResult {
+    run(
+        &["--file", LITERALS, FOX, NOBODY],
+        "tests/expected/fox.nobody.literals",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pattern_file_fixed_strings() -> TestResult {
+    run(
+        &["-F", "-f", LITERALS, FOX, NOBODY],
+        "tests/expected/fox.nobody.literals.fixed_strings",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_word() -> TestResult {
+    run(
+        &["-w", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.word",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_word_count_insensitive() -> TestResult {
+    run(
+        &["--word-regexp", "-ci", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.word.count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn nobody_word_invert_fixed_strings() -> TestResult {
+    run(
+        &["-wv", "-F", "-e", "tell", "-e", "you", NOBODY],
+        "tests/expected/nobody.txt.tell.you.word.invert",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn nobody_line_crlf() -> TestResult {
+    run(
+        &["--line-regexp", "-i", "to.an.admiring.bog!", NOBODY],
+        "tests/expected/nobody.txt.bog.line",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_with_matches() -> TestResult {
+    let expected = format!("{}\n{}\n{}\n", BUSTLE, FOX, NOBODY);
+    Command::cargo_bin(PRG)?
+        .args(["-l", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_without_match() -> TestResult {
+    let expected = format!("{}\n{}\n{}\n", BUSTLE, EMPTY, FOX);
+    Command::cargo_bin(PRG)?
+        .args([
+            "--files-without-match",
+            "Nobody",
+            BUSTLE,
+            EMPTY,
+            FOX,
+            NOBODY,
+        ])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_with_matches_stdin() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-l", "fox"])
+        .write_stdin(fs::read_to_string(FOX)?)
+        .assert()
+        .success()
+        .stdout("(standard input)\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "fox", FOX])
+        .assert()
+        .code(0)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--quiet", "cat", FOX])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet_match_ignores_error() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["-q", "fox", &bad, FOX])
+        .assert()
+        .code(0);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn status_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["cat", FOX])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn status_error() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["fox", FOX, &bad])
+        .assert()
+        .code(2)
+        .stdout(format!(
+            "{}:The quick brown fox jumps over the lazy dog.\n",
+            FOX
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn status_bad_pattern() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["*foo", FOX])
+        .assert()
+        .code(2);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_max_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-m", "x", "the", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("x: invalid max count"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_after_context() -> TestResult {
+    run(
+        &["-m1", "-A2", "-n", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.max_count.after_context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_count_multiple() -> TestResult {
+    run(
+        &["--max-count", "2", "-ci", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.max_count.count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_invert() -> TestResult {
+    run(
+        &["-m", "1", "-vn", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.max_count.invert",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_zero() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-m0", "the", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_matches() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["hello", NUL_DAT])
+        .assert()
+        .success()
+        .stdout(format!("Binary file {} matches\n", NUL_DAT));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "hello", NUL_DAT])
+        .assert()
+        .success()
+        .stdout("2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_text() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-a", "-v", "hello", NUL_DAT])
+        .assert()
+        .success()
+        .stdout(&b"foo\0bar\n"[..]);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_late_nul() -> TestResult {
+    // The NUL sits in a line that does not match, well past the first buffer
+    let mut content = vec![];
+    for i in 0..3000 {
+        match i {
+            2000 => content.extend_from_slice(b"nul \0 here\n"),
+            _ => content.extend_from_slice(format!("line {}\n", i).as_bytes()),
+        }
+    }
+    content.extend_from_slice(b"hello\n");
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("late.dat");
+    fs::write(&file, content)?;
+
+    Command::cargo_bin(PRG)?
+        .arg("hello")
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout(format!("Binary file {} matches\n", file.display()));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_without_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--binary-files=without-match", "hello", NUL_DAT, FOX])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn non_utf8_file() -> TestResult {
+    let mut expected = format!("Binary file {} matches\n{}:1:caf", NUL_DAT, LATIN1).into_bytes();
+    expected.extend_from_slice(b"\xe9 hello\n");
+    expected.extend_from_slice(format!("{}:2:ok hello\n", LATIN1).as_bytes());
+
+    Command::cargo_bin(PRG)?
+        .args(["-n", "hello", NUL_DAT, LATIN1])
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_glob() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--include", "a[b", "fox", IGNORE_DIR])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Invalid glob \"a[b\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_include() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-rl", "--include=*.rs", "fox", IGNORE_DIR])
+        .assert()
+        .success()
+        .stdout("tests/ignore/src/main.rs\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-r",
+            "--exclude=*.txt",
+            "--exclude=.*",
+            "--exclude=*.md",
+            "--exclude-dir=build",
+            "fox",
+            IGNORE_DIR,
+        ])
+        .assert()
+        .success()
+        .stdout("the fox\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_gitignore() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-rl", "--gitignore", "--exclude-dir=src", "fox", IGNORE_DIR])
+        .assert()
+        .success()
+        .stdout("tests/ignore/kept.txt\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exclude_command_line_file() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--exclude=fox.*", "fox", FOX])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_threads() -> TestResult {
+    for bad in ["0", "x"] {
+        Command::cargo_bin(PRG)?
+            .args(["-j", bad, "the", FOX])
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains(format!(
+                "{bad}: invalid thread count"
+            )));
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+fn same_as_one_thread(args: &[&str]) -> TestResult {
+    let one = Command::cargo_bin(PRG)?.arg("-j1").args(args).output()?;
+    for threads in ["-j2", "-j8"] {
+        let many = Command::cargo_bin(PRG)?.arg(threads).args(args).output()?;
+        assert_eq!(one.status.code(), many.status.code());
+        assert_eq!(
+            String::from_utf8_lossy(&one.stdout),
+            String::from_utf8_lossy(&many.stdout)
+        );
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn threads_recursive_count() -> TestResult {
+    same_as_one_thread(&["-rc", "the", "tests"])
+}
+
+// --------------------------------------------------
+#[test]
+fn threads_recursive_context() -> TestResult {
+    same_as_one_thread(&["-rn", "-C1", "e", INPUTS_DIR, FOX])
+}
+
+// --------------------------------------------------
+#[test]
+fn threads_listing() -> TestResult {
+    same_as_one_thread(&["-rL", "fox", "tests"])
+}
+
+// --------------------------------------------------
+#[test]
+fn threads_unordered() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args([
+            "-j4",
+            "--unordered",
+            "-c",
+            "the",
+            BUSTLE,
+            EMPTY,
+            FOX,
+            NOBODY,
+        ])
+        .output()?;
+    assert!(output.status.success());
+    let mut lines: Vec<_> = std::str::from_utf8(&output.stdout)?.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "tests/inputs/bustle.txt:1",
+            "tests/inputs/empty.txt:0",
+            "tests/inputs/fox.txt:1",
+            "tests/inputs/nobody.txt:3",
+        ]
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+// Standard input is still open when its matches should show up, so a worker
+// that held back the whole file would never get here
+#[test]
+fn threads_stream_first_file() -> TestResult {
+    use std::{
+        io::{self, Read, Write},
+        process::{self, Stdio},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    let mut child = process::Command::new(env!("CARGO_BIN_EXE_grepr"))
+        .args(["-j2", "match", "-", FOX])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = vec![0; 64 * 1024];
+        tx.send(stdout.read_exact(&mut buf).is_ok()).ok();
+        io::copy(&mut stdout, &mut io::sink()).ok();
+    });
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all("match\n".repeat(20_000).as_bytes())?;
+    let early = rx.recv_timeout(Duration::from_secs(10));
+    drop(stdin);
+    assert!(child.wait()?.success());
+    assert_eq!(early, Ok(true));
+    Ok(())
+}
+
+// --------------------------------------------------
+fn json_records(args: &[&str]) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    let output = Command::cargo_bin(PRG)?.arg("--json").args(args).output()?;
+    assert!(output.stderr.is_empty());
+    let records = std::str::from_utf8(&output.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    Ok(records)
+}
+
+// --------------------------------------------------
+#[test]
+fn json_records_per_file() -> TestResult {
+    let records = json_records(&["-B1", "over", FOX, BUSTLE])?;
+    let types: Vec<_> = records
+        .iter()
+        .map(|r| r["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["begin", "match", "end", "begin", "end", "summary"]);
+
+    let data = &records[1]["data"];
+    assert_eq!(data["path"], FOX);
+    assert_eq!(data["line_number"], 1);
+    assert_eq!(data["byte_offset"], 0);
+    assert_eq!(data["text"], "The quick brown fox jumps over the lazy dog.");
+    assert_eq!(
+        data["submatches"],
+        serde_json::json!([{"text": "over", "start": 26, "end": 30}])
+    );
+    assert_eq!(records[2]["data"]["matched_lines"], 1);
+    assert_eq!(records[4]["data"]["matched_lines"], 0);
+    assert_eq!(
+        records[5]["data"],
+        serde_json::json!({"searches": 2, "searches_with_match": 1, "matched_lines": 1})
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_context_records() -> TestResult {
+    let records = json_records(&["-A1", "house", BUSTLE])?;
+    assert_eq!(records[1]["type"], "match");
+    assert_eq!(records[2]["type"], "context");
+    assert_eq!(records[2]["data"]["line_number"], 2);
+    assert_eq!(records[2]["data"]["byte_offset"], 22);
+    assert_eq!(records[2]["data"]["submatches"], serde_json::json!([]));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_error_records() -> TestResult {
+    let bad = gen_bad_file();
+    let output = Command::cargo_bin(PRG)?
+        .args(["--json", "fox", &bad, INPUTS_DIR])
+        .assert()
+        .code(2)
+        .stderr("")
+        .get_output()
+        .stdout
+        .clone();
+    let records: Vec<serde_json::Value> = std::str::from_utf8(&output)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0]["type"], "error");
+    assert_eq!(records[0]["data"]["path"], bad.as_str());
+    assert_eq!(records[1]["type"], "error");
+    assert_eq!(
+        records[1]["data"],
+        serde_json::json!({"path": INPUTS_DIR, "message": "is a directory"})
+    );
+    assert_eq!(records[2]["type"], "summary");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_json_with_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--json", "-c", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn replace_capture_groups() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--replace", "$2 $1", r"(\w+) (fox)", FOX])
+        .assert()
+        .success()
+        .stdout("The quick fox brown jumps over the lazy dog.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn replace_named_groups_only_matching() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--replace", "<${w}>", r"(?<w>\w+)s\b", FOX, BUSTLE])
+        .assert()
+        .success()
+        .stdout(
+            "tests/inputs/fox.txt:<jump>\n\
+             tests/inputs/bustle.txt:<I>\n\
+             tests/inputs/bustle.txt:<industrie>\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("bustle.txt");
+    fs::copy(BUSTLE, &file)?;
+    Command::cargo_bin(PRG)?
+        .args([
+            "--in-place",
+            "--replace",
+            "the $1",
+            "-m1",
+            r"(?i)\bthe (\w+)",
+        ])
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout("");
+    let expected = fs::read_to_string(BUSTLE)?.replacen("The bustle", "the bustle", 1);
+    assert_eq!(fs::read_to_string(&file)?, expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_place_no_match() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("fox.txt");
+    fs::copy(FOX, &file)?;
+    Command::cargo_bin(PRG)?
+        .args(["--in-place", "--replace", "cat", "wolf"])
+        .arg(&file)
+        .assert()
+        .code(1);
+    assert_eq!(fs::read_to_string(&file)?, fs::read_to_string(FOX)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_in_place_quiet() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let (first, second) = (dir.path().join("fox1.txt"), dir.path().join("fox2.txt"));
+    fs::copy(FOX, &first)?;
+    fs::copy(FOX, &second)?;
+    Command::cargo_bin(PRG)?
+        .args(["-q", "--in-place", "--replace", "cat", "fox"])
+        .args([&first, &second])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+    // Neither file is touched, rather than only the first being rewritten
+    assert_eq!(fs::read_to_string(&first)?, fs::read_to_string(FOX)?);
+    assert_eq!(fs::read_to_string(&second)?, fs::read_to_string(FOX)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_in_place_without_replace() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--in-place", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--replace <TEMPLATE>"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_line_numbers() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-U", "-n", r"fn \w+\(\n\s+self", MULTILINE])
+        .assert()
+        .success()
+        .stdout("1:fn a(\n2:    self,\n5:fn c(\n6:    self) {}\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-U", "-c", r"(?s)fn b.*?self", MULTILINE])
+        .assert()
+        .success()
+        .stdout("3\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline_only_matching() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-U", "-o", "-n", r"\(\n\s+self\)", MULTILINE])
+        .assert()
+        .success()
+        .stdout("5:(\n    self)\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn without_multiline_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([r"fn \w+\(\n\s+self", MULTILINE])
+        .assert()
+        .code(1);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn with_filename() -> TestResult {
+    run(
+        &["-H", "fox", FOX],
+        "tests/expected/fox.txt.fox.with_filename",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn no_filename() -> TestResult {
+    run(
+        &["-hn", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.no_filename",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn null_count() -> TestResult {
+    run(
+        &["-Zc", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.null.count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn null_files_with_matches() -> TestResult {
+    run(
+        &["-lZ", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.null.files_with_matches",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn null_after_context() -> TestResult {
+    run(
+        &["-Z", "-A1", "-n", "house", BUSTLE, FOX],
+        "tests/expected/bustle.fox.house.null.after_context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn help_long_only() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("--no-filename"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip_formats() -> TestResult {
+    let files = [
+        "tests/zip/fox.txt.gz",
+        "tests/zip/fox.txt.bz2",
+        "tests/zip/fox.txt.xz",
+        "tests/zip/fox.txt.zst",
+    ];
+    let expected: String = files
+        .iter()
+        .map(|f| {
+            format!(
+                "{}:1:{}",
+                f, "The quick brown fox jumps over the lazy dog.\n"
+            )
+        })
+        .collect();
+    Command::cargo_bin(PRG)?
+        .args(["-z", "-n", "fox"])
+        .args(files)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip_by_magic() -> TestResult {
+    // Two gzip members with no .gz extension
+    Command::cargo_bin(PRG)?
+        .args(["-zci", "the", "tests/zip/rotated.1"])
+        .assert()
+        .success()
+        .stdout("6\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip_recursive() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-zrl",
+            "--include=*.gz",
+            "--include=*.1",
+            "Nobody",
+            "tests/zip",
+        ])
+        .assert()
+        .success()
+        .stdout("tests/zip/rotated.1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip_plain_file() -> TestResult {
+    run(&["-z", "", FOX], "tests/expected/empty_regex.fox.txt")
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_lookaround_without_pcre() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["(?<=quick )brown", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "Invalid pattern \"(?<=quick )brown\" (lookaround and backreferences need -P)",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(not(feature = "pcre"))]
+#[test]
+fn dies_pcre_not_built() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("rebuild with --features pcre"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "pcre")]
+#[test]
+fn pcre_lookaround() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "-o", "-b", "(?<=quick )brown", FOX])
+        .assert()
+        .success()
+        .stdout("10:brown\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "pcre")]
+#[test]
+fn pcre_backreference() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "-c", r"(\w)\1", BUSTLE, NOBODY])
+        .assert()
+        .success()
+        .stdout("tests/inputs/bustle.txt:3\ntests/inputs/nobody.txt:3\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "pcre")]
+#[test]
+fn dies_bad_pcre_pattern() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "(?<=", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Invalid PCRE pattern \"(?<=\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "pcre")]
+#[test]
+fn pcre_not_utf8() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-P", r"(\w+) \1"])
+        .write_stdin(b"caf\xe9 the the\n".as_slice())
+        .assert()
+        .success()
+        .stdout(b"caf\xe9 the the\n".as_slice());
+
+    Command::cargo_bin(PRG)?
+        .args(["-P", "-n", r"^caf. hel+o$", LATIN1])
+        .assert()
+        .success()
+        .stdout(b"1:caf\xe9 hello\n".as_slice());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "pcre")]
+#[test]
+fn pcre_in_place_not_utf8() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("latin1.txt");
+    fs::copy(LATIN1, &file)?;
+    Command::cargo_bin(PRG)?
+        .args([
+            "-P",
+            "--in-place",
+            "--replace",
+            "$2 ${1}!",
+            r"(caf.) (hel+o)",
+        ])
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(fs::read(&file)?, b"hello caf\xe9!\nok hello\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "pcre")]
+#[test]
+fn pcre_backtrack_limit() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let slow = dir.path().join("slow.txt");
+    fs::write(&slow, format!("{}\n", "a".repeat(40)))?;
+    Command::cargo_bin(PRG)?
+        .args(["-P", "(a|aa)*(?=b)c", FOX])
+        .arg(&slow)
+        .assert()
+        .code(2)
+        .stdout("")
+        .stderr(format!(
+            "{}: Error executing regex: Max limit for backtracking count exceeded\n",
+            slow.display()
+        ));
+    Ok(())
+}
@@ -0,0 +1 @@
+the fox